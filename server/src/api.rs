@@ -1,35 +1,378 @@
-use crate::db::Database;
-use crate::translator::Translator;
-use crate::RegexTerm;
+use crate::chat;
+use crate::db::{Database, Keyed};
+use crate::error::{ApiError, Resource};
+use crate::feedback::{self, Feedback};
+use crate::memory::{self, MemoryEntry};
+use crate::metrics;
+use crate::pipeline::{Pipeline, Request};
+use crate::query::TermQuery;
+use crate::stats::{Stats, TermStats};
+use crate::subtitle::{self, Subtitle};
+use crate::traffic::Traffic;
+use crate::usage::Usage;
+use crate::{Entity, RegexTerm};
 use serde::Serializer;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use warp::Filter;
 
-async fn handle_api_get_terms(db: Arc<Database<String, RegexTerm>>) -> anyhow::Result<Vec<u8>> {
+#[derive(Deserialize)]
+struct CandidatesQuery {
+    /// Minimum number of occurrences.
+    #[serde(default)]
+    min: Option<u64>,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+async fn handle_api_get_term_candidates(
+    db: Arc<Database<String, RegexTerm>>,
+    entities: Arc<Database<String, Entity>>,
+    traffic: Option<Arc<Traffic>>,
+    query: CandidatesQuery,
+) -> anyhow::Result<Vec<u8>> {
+    let traffic = match traffic {
+        Some(v) => v,
+        None => anyhow::bail!(ApiError::Disabled("Traffic recording")),
+    };
+
+    let mut terms: Vec<_> = db.iter()?.map(|x| x.value.clone()).collect();
+    for entity in entities.iter()? {
+        for lang in entity.value.renderings.keys() {
+            terms.extend(entity.value.terms(lang)?);
+        }
+    }
+
+    let mut candidates = traffic.candidates(&terms, query.min.unwrap_or(2))?;
+    candidates.truncate(query.limit.unwrap_or(100));
+    Ok(serde_json::to_vec(&candidates)?)
+}
+
+/// A term together with statistics of its matches.
+#[derive(Serialize)]
+struct TermWithStats<'a> {
+    #[serde(flatten)]
+    term: &'a RegexTerm,
+    stats: TermStats,
+}
+
+async fn handle_api_get_terms(
+    db: Arc<Database<String, RegexTerm>>,
+    stats: Arc<Stats>,
+    query: TermQuery,
+) -> anyhow::Result<(Vec<u8>, usize, Option<String>)> {
+    let guard = match query.needs_stats() {
+        true => Some(stats.all()?),
+        false => None,
+    };
+    let empty = HashMap::new();
+    let stats = guard.as_deref().unwrap_or(&empty);
+    let terms = db.db.borrow_data()?;
+    let page = query.run(terms.iter(), stats)?;
+
     let mut vec = Vec::new();
     let mut ser = serde_json::Serializer::new(&mut vec);
-    ser.collect_seq(db.iter()?)?;
-    Ok(vec)
+    if query.stats {
+        ser.collect_seq(page.terms.iter().map(|&(key, term)| Keyed {
+            key,
+            value: TermWithStats {
+                term,
+                stats: stats.get(key).cloned().unwrap_or_default(),
+            },
+        }))?;
+    } else {
+        ser.collect_seq(page.terms.iter().map(|&(key, value)| Keyed { key, value }))?;
+    }
+    Ok((vec, page.total, page.next))
+}
+
+/// Number of terms and their matches within a group of the summary.
+#[derive(Serialize, Default)]
+struct StatsGroup {
+    terms: u64,
+    /// Terms that have never matched.
+    unused: u64,
+    matches: u64,
+}
+
+impl StatsGroup {
+    fn add(&mut self, stats: Option<&TermStats>) {
+        self.terms += 1;
+        match stats {
+            Some(stats) if stats.count > 0 => self.matches += stats.count,
+            _ => self.unused += 1,
+        }
+    }
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct StatsSummary {
+    #[serde(flatten)]
+    total: StatsGroup,
+    /// Matches keyed by the language translated into.
+    languages: BTreeMap<String, u64>,
+    /// Groups keyed by `targetLang`, or `*` for terms of all languages.
+    by_target_lang: BTreeMap<String, StatsGroup>,
+    by_type: BTreeMap<&'static str, StatsGroup>,
+    /// Groups keyed by the translator filter, or `*` for terms of all translators.
+    by_translator: BTreeMap<String, StatsGroup>,
+}
+
+async fn handle_api_get_term_stats(
+    db: Arc<Database<String, RegexTerm>>,
+    stats: Arc<Stats>,
+) -> anyhow::Result<Vec<u8>> {
+    let stats = stats.all()?;
+    let mut summary = StatsSummary::default();
+    for term in db.iter()? {
+        let stats = stats.get(term.key);
+        if let Some(stats) = stats {
+            for (lang, count) in &stats.languages {
+                *summary.languages.entry(lang.clone()).or_default() += count;
+            }
+        }
+        let term = term.value;
+        summary.total.add(stats);
+        summary
+            .by_target_lang
+            .entry(term.target_lang.clone().unwrap_or_else(|| "*".to_owned()))
+            .or_default()
+            .add(stats);
+        summary
+            .by_type
+            .entry(term.ty.as_str())
+            .or_default()
+            .add(stats);
+        summary
+            .by_translator
+            .entry(
+                term.translator
+                    .as_ref()
+                    .map_or_else(|| "*".to_owned(), |x| x.to_string()),
+            )
+            .or_default()
+            .add(stats);
+    }
+    Ok(serde_json::to_vec(&summary)?)
+}
+
+/// Entity tag of a version of a term, for conditional requests.
+fn term_etag(term: &RegexTerm) -> String {
+    format!("\"{}\"", term.version)
+}
+
+/// Check the `If-Match` header of a request against the current version of a term.
+///
+/// The header is required, so that a client cannot overwrite changes it has not seen.
+fn check_if_match(if_match: Option<&str>, id: &str, term: &RegexTerm) -> anyhow::Result<()> {
+    let if_match = match if_match {
+        Some(v) => v,
+        None => anyhow::bail!(ApiError::PreconditionRequired),
+    };
+    let etag = term_etag(term);
+    // Versions are compared as they are, so weak validators match as well.
+    if if_match
+        .split(',')
+        .map(|x| x.trim().trim_start_matches("W/"))
+        .any(|x| x == "*" || x == etag)
+    {
+        return Ok(());
+    }
+    anyhow::bail!(ApiError::Modified {
+        id: id.to_owned(),
+        current: serde_json::to_value(Keyed {
+            key: id,
+            value: term
+        })?,
+    })
+}
+
+/// Apply a JSON merge patch as specified by RFC 7396.
+fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let patch = match patch {
+        serde_json::Value::Object(v) => v,
+        v => {
+            *target = v;
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(&key);
+        } else {
+            merge_patch(target.entry(key).or_insert(serde_json::Value::Null), value);
+        }
+    }
 }
 
 async fn handle_api_get_term(
     db: Arc<Database<String, RegexTerm>>,
     id: String,
+) -> anyhow::Result<(Vec<u8>, String)> {
+    let term = match db.get(&id)? {
+        Some(v) => v,
+        None => {
+            anyhow::bail!(ApiError::NotFound(Resource::Term, id));
+        }
+    };
+    Ok((serde_json::to_vec(&*term)?, term_etag(term.value)))
+}
+
+/// Parse a term, reporting where its regex is invalid.
+fn parse_term(body: serde_json::Value) -> Result<RegexTerm, ApiError> {
+    if let Some(input) = body.get("input").and_then(|x| x.as_str()) {
+        if let Err(err) = fancy_regex::Regex::new(input) {
+            let position = match err {
+                fancy_regex::Error::ParseError(position, _) => Some(position),
+                _ => None,
+            };
+            return Err(ApiError::InvalidTerm {
+                message: err.to_string(),
+                position,
+            });
+        }
+    }
+    serde_json::from_value(body).map_err(|err| ApiError::InvalidTerm {
+        message: err.to_string(),
+        position: None,
+    })
+}
+
+async fn handle_api_post_term(
+    db: Arc<Database<String, RegexTerm>>,
+    body: serde_json::Value,
+) -> anyhow::Result<(Vec<u8>, String)> {
+    let mut body = parse_term(body)?;
+    body.touch(None);
+    let key = db.db.write(|map| {
+        for i in 0.. {
+            let key = i.to_string();
+            if !map.contains_key(&key) {
+                map.insert(key.to_string(), body);
+                return key;
+            }
+        }
+        unreachable!()
+    })?;
+    db.save()?;
+
+    let term = db.get(&key)?.unwrap();
+    Ok((serde_json::to_vec(&*term)?, term_etag(term.value)))
+}
+
+/// Replace a term with the result of `update`, unless it has changed since the version in
+/// `If-Match`.
+fn update_term(
+    db: &Database<String, RegexTerm>,
+    id: &str,
+    if_match: Option<&str>,
+    update: impl FnOnce(&RegexTerm) -> anyhow::Result<RegexTerm>,
+) -> anyhow::Result<(Vec<u8>, String)> {
+    let term = db.db.write(|map| {
+        let current = match map.get_mut(id) {
+            Some(v) => v,
+            None => anyhow::bail!(ApiError::NotFound(Resource::Term, id.to_owned())),
+        };
+        check_if_match(if_match, id, current)?;
+        let mut term = update(current)?;
+        term.touch(Some(current));
+        *current = term.clone();
+        Ok(term)
+    })??;
+    db.save()?;
+
+    let vec = serde_json::to_vec(&Keyed {
+        key: id,
+        value: &term,
+    })?;
+    Ok((vec, term_etag(&term)))
+}
+
+async fn handle_api_put_term(
+    db: Arc<Database<String, RegexTerm>>,
+    id: String,
+    if_match: Option<String>,
+    body: serde_json::Value,
+) -> anyhow::Result<(Vec<u8>, String)> {
+    let body = parse_term(body)?;
+    update_term(&db, &id, if_match.as_deref(), |_| Ok(body))
+}
+
+async fn handle_api_patch_term(
+    db: Arc<Database<String, RegexTerm>>,
+    id: String,
+    if_match: Option<String>,
+    body: serde_json::Value,
+) -> anyhow::Result<(Vec<u8>, String)> {
+    update_term(&db, &id, if_match.as_deref(), |current| {
+        let mut value = serde_json::to_value(current)?;
+        merge_patch(&mut value, body);
+        Ok(parse_term(value)?)
+    })
+}
+
+async fn handle_api_delete_term(
+    db: Arc<Database<String, RegexTerm>>,
+    stats: Arc<Stats>,
+    id: String,
+    if_match: Option<String>,
+) -> anyhow::Result<Vec<u8>> {
+    db.db.write(|map| {
+        let current = match map.get(&id) {
+            Some(v) => v,
+            None => anyhow::bail!(ApiError::NotFound(Resource::Term, id.clone())),
+        };
+        check_if_match(if_match.as_deref(), &id, current)?;
+        map.remove(&id);
+        Ok(())
+    })??;
+    db.save()?;
+    stats.remove(&id)?;
+
+    Ok("{}".into())
+}
+
+async fn handle_api_get_entities(db: Arc<Database<String, Entity>>) -> anyhow::Result<Vec<u8>> {
+    let mut vec = Vec::new();
+    let mut ser = serde_json::Serializer::new(&mut vec);
+    ser.collect_seq(db.iter()?)?;
+    Ok(vec)
+}
+
+async fn handle_api_get_entity(
+    db: Arc<Database<String, Entity>>,
+    id: String,
 ) -> anyhow::Result<Vec<u8>> {
     let vec = match db.get(&id)? {
         Some(v) => serde_json::to_vec(&*v)?,
         None => {
-            anyhow::bail!("Term ID does not exist");
+            anyhow::bail!(ApiError::NotFound(Resource::Entity, id));
         }
     };
     Ok(vec)
 }
 
-async fn handle_api_post_term(
-    db: Arc<Database<String, RegexTerm>>,
-    body: RegexTerm,
+/// Check that terms can be generated from the entity for all languages it renders to.
+fn validate_entity(entity: &Entity) -> anyhow::Result<()> {
+    for lang in entity.renderings.keys() {
+        entity
+            .terms(lang)
+            .map_err(|err| ApiError::InvalidBody(err.to_string()))?;
+    }
+    Ok(())
+}
+
+async fn handle_api_post_entity(
+    db: Arc<Database<String, Entity>>,
+    body: Entity,
 ) -> anyhow::Result<Vec<u8>> {
+    validate_entity(&body)?;
     let key = db.db.write(|map| {
         for i in 0.. {
             let key = i.to_string();
@@ -40,51 +383,190 @@ async fn handle_api_post_term(
         }
         unreachable!()
     })?;
-    db.db.save()?;
+    db.save()?;
 
-    let term = db.get(&key)?.unwrap();
-    let vec = serde_json::to_vec(&*term)?;
+    let entity = db.get(&key)?.unwrap();
+    let vec = serde_json::to_vec(&*entity)?;
     Ok(vec)
 }
 
-async fn handle_api_put_term(
-    db: Arc<Database<String, RegexTerm>>,
+async fn handle_api_put_entity(
+    db: Arc<Database<String, Entity>>,
     id: String,
-    body: RegexTerm,
+    body: Entity,
 ) -> anyhow::Result<Vec<u8>> {
+    validate_entity(&body)?;
     db.db.write(|map| {
         match map.get_mut(&id) {
             Some(v) => {
                 *v = body;
             }
             None => {
-                anyhow::bail!("Term ID does not exist");
+                anyhow::bail!(ApiError::NotFound(Resource::Entity, id.clone()));
             }
         }
         Ok(())
     })??;
-    db.db.save()?;
+    db.save()?;
 
-    let term = db.get(&id)?.unwrap();
-    let vec = serde_json::to_vec(&*term)?;
+    let entity = db.get(&id)?.unwrap();
+    let vec = serde_json::to_vec(&*entity)?;
     Ok(vec)
 }
 
-async fn handle_api_delete_term(
-    db: Arc<Database<String, RegexTerm>>,
+async fn handle_api_delete_entity(
+    db: Arc<Database<String, Entity>>,
+    id: String,
+) -> anyhow::Result<Vec<u8>> {
+    db.db.write(|map| {
+        if map.remove(&id).is_none() {
+            anyhow::bail!(ApiError::NotFound(Resource::Entity, id.clone()));
+        }
+        Ok(())
+    })??;
+    db.save()?;
+
+    Ok("{}".into())
+}
+
+async fn handle_api_get_memory(db: Arc<Database<String, MemoryEntry>>) -> anyhow::Result<Vec<u8>> {
+    let mut vec = Vec::new();
+    let mut ser = serde_json::Serializer::new(&mut vec);
+    ser.collect_seq(db.iter()?)?;
+    Ok(vec)
+}
+
+async fn handle_api_post_memory(
+    db: Arc<Database<String, MemoryEntry>>,
+    body: MemoryEntry,
+) -> anyhow::Result<Vec<u8>> {
+    if body.source.trim().is_empty() || body.target.trim().is_empty() {
+        anyhow::bail!(ApiError::InvalidBody("Memory entry cannot be empty".into()));
+    }
+    let key = memory::insert(&db, vec![body])?.remove(0);
+
+    let entry = db.get(&key)?.unwrap();
+    let vec = serde_json::to_vec(&*entry)?;
+    Ok(vec)
+}
+
+async fn handle_api_delete_memory(
+    db: Arc<Database<String, MemoryEntry>>,
     id: String,
 ) -> anyhow::Result<Vec<u8>> {
     db.db.write(|map| {
         if map.remove(&id).is_none() {
-            anyhow::bail!("Term ID does not exist");
+            anyhow::bail!(ApiError::NotFound(Resource::MemoryEntry, id.clone()));
         }
         Ok(())
     })??;
-    db.db.save()?;
+    db.save()?;
 
     Ok("{}".into())
 }
 
+async fn handle_api_get_memory_tmx(
+    db: Arc<Database<String, MemoryEntry>>,
+) -> anyhow::Result<Vec<u8>> {
+    Ok(memory::to_tmx(&db)?.into_bytes())
+}
+
+#[derive(Serialize)]
+struct ImportResponse {
+    imported: usize,
+}
+
+async fn handle_api_post_memory_tmx(
+    db: Arc<Database<String, MemoryEntry>>,
+    body: warp::hyper::body::Bytes,
+) -> anyhow::Result<Vec<u8>> {
+    let entries = std::str::from_utf8(&body)
+        .map_err(anyhow::Error::from)
+        .and_then(memory::parse_tmx)
+        .map_err(|err| ApiError::InvalidBody(format!("{:#}", err)))?;
+    let imported = memory::insert(&db, entries)?.len();
+    Ok(serde_json::to_vec(&ImportResponse { imported })?)
+}
+
+async fn handle_api_get_feedback(db: Arc<Database<String, Feedback>>) -> anyhow::Result<Vec<u8>> {
+    let mut vec = Vec::new();
+    let mut ser = serde_json::Serializer::new(&mut vec);
+    ser.collect_seq(db.iter()?)?;
+    Ok(vec)
+}
+
+async fn handle_api_post_feedback(
+    db: Arc<Database<String, Feedback>>,
+    mut body: Feedback,
+) -> anyhow::Result<Vec<u8>> {
+    body.time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let key = db.db.write(|map| {
+        for i in 0.. {
+            let key = i.to_string();
+            if !map.contains_key(&key) {
+                map.insert(key.to_string(), body);
+                return key;
+            }
+        }
+        unreachable!()
+    })?;
+    db.save()?;
+
+    let feedback = db.get(&key)?.unwrap();
+    let vec = serde_json::to_vec(&*feedback)?;
+    Ok(vec)
+}
+
+#[derive(Deserialize)]
+struct FeedbackTermsQuery {
+    /// Minimum number of corrections making the same change.
+    #[serde(default)]
+    min: Option<usize>,
+}
+
+async fn handle_api_get_feedback_terms(
+    db: Arc<Database<String, Feedback>>,
+    terms: Arc<Database<String, RegexTerm>>,
+    query: FeedbackTermsQuery,
+) -> anyhow::Result<Vec<u8>> {
+    let suggestions = feedback::suggest_terms(&db, &terms, query.min.unwrap_or(1))?;
+    Ok(serde_json::to_vec(&suggestions)?)
+}
+
+#[derive(Serialize)]
+struct UsageResponse {
+    provider: String,
+    month: String,
+    characters: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u64>,
+}
+
+async fn handle_api_get_metrics() -> anyhow::Result<Vec<u8>> {
+    metrics::encode()
+}
+
+async fn handle_api_get_usage(usage: Arc<Usage>) -> anyhow::Result<Vec<u8>> {
+    let runtime = crate::runtime();
+    let response: Vec<_> = usage
+        .list()?
+        .into_iter()
+        .map(|x| UsageResponse {
+            limit: runtime
+                .config
+                .quota
+                .get(&x.key.to_lowercase())
+                .and_then(|x| x.monthly),
+            provider: x.key,
+            month: x.value.month,
+            characters: x.value.characters,
+        })
+        .collect();
+    Ok(serde_json::to_vec(&response)?)
+}
+
 #[derive(Deserialize)]
 struct TranslateQuery {
     #[serde(rename = "to")]
@@ -94,58 +576,137 @@ struct TranslateQuery {
 #[derive(Deserialize)]
 struct TranslateBody {
     text: String,
-}
-
-#[derive(Serialize)]
-struct TranslateResponse {
-    translation: String,
+    /// Handle of the author, used to infer the context of the text.
+    #[serde(default)]
+    author: Option<String>,
 }
 
 async fn handle_api_post_translate(
-    db: Arc<Database<String, RegexTerm>>,
+    pipeline: Arc<Pipeline>,
+    traffic: Option<Arc<Traffic>>,
     query: TranslateQuery,
     body: TranslateBody,
 ) -> anyhow::Result<Vec<u8>> {
-    // Verify that the target language is supported.
-    let translator = match query.target_lang.as_str() {
-        "en" => &**crate::TRANSLATOR_EN,
-        "zh" => &**crate::TRANSLATOR_ZH,
-        _ => anyhow::bail!("Invalid query"),
-    };
+    let runtime = crate::runtime();
+    if runtime.translator(&query.target_lang).is_none() {
+        anyhow::bail!(ApiError::UnsupportedLanguage(query.target_lang));
+    }
 
-    // Filtering out terms that shouldn't be applied in the specified context.
-    let mut eligible_terms: Vec<_> = db
-        .iter()?
-        .map(|v| v.value)
-        .filter(|t| {
-            t.target_lang
-                .as_ref()
-                .map(|x| x == &query.target_lang)
-                .unwrap_or(true)
-        })
-        .filter(|t| {
-            t.translator
-                .as_ref()
-                .map(|x| x.contains(translator.name()))
-                .unwrap_or(true)
-        })
-        .cloned()
-        .collect();
-    eligible_terms.sort_unstable_by(RegexTerm::compare_priority);
+    if let Some(traffic) = traffic {
+        traffic.record(&body.text);
+    }
 
-    let dict_translator = crate::translator::DictionaryTranslator::new(translator, &eligible_terms);
-    let translation = dict_translator.translate(&body.text).await?;
-    Ok(serde_json::to_vec(&TranslateResponse { translation })?)
+    let translation = pipeline
+        .translate(
+            &runtime,
+            Request {
+                target_lang: &query.target_lang,
+                text: &body.text,
+                author: body.author.as_deref(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    Ok(serde_json::to_vec(&translation)?)
+}
+
+#[derive(Deserialize)]
+struct SubtitlesQuery {
+    #[serde(rename = "to")]
+    target_lang: String,
+    /// Detected from the content if unset.
+    #[serde(default)]
+    format: Option<subtitle::Format>,
+    #[serde(default)]
+    author: Option<String>,
+    /// Number of neighboring cues translated together. Batches merged into fewer lines by the
+    /// translator are sent again cue by cue.
+    #[serde(default)]
+    batch: Option<usize>,
+}
+
+async fn handle_api_post_translate_subtitles(
+    pipeline: Arc<Pipeline>,
+    query: SubtitlesQuery,
+    body: warp::hyper::body::Bytes,
+) -> anyhow::Result<(Vec<u8>, subtitle::Format)> {
+    let runtime = crate::runtime();
+    if runtime.translator(&query.target_lang).is_none() {
+        anyhow::bail!(ApiError::UnsupportedLanguage(query.target_lang));
+    }
+
+    let text = std::str::from_utf8(&body).map_err(|err| ApiError::InvalidBody(err.to_string()))?;
+    let format = query
+        .format
+        .unwrap_or_else(|| subtitle::Format::detect(text));
+    let mut subtitle = Subtitle::parse(format, text);
+
+    subtitle
+        .translate(
+            &pipeline,
+            &runtime,
+            &query.target_lang,
+            query.author.as_deref(),
+            query.batch.unwrap_or(20),
+        )
+        .await?;
+    Ok((subtitle.render().into_bytes(), format))
+}
+
+pub fn api_get_term_candidates(
+    db: Arc<Database<String, RegexTerm>>,
+    entities: Arc<Database<String, Entity>>,
+    traffic: Option<Arc<Traffic>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("terms" / "candidates")
+        .and(warp::get())
+        .and(warp::query())
+        .and(warp::any().map(move || db.clone()))
+        .and(warp::any().map(move || entities.clone()))
+        .and(warp::any().map(move || traffic.clone()))
+        .and_then(move |query, db, entities, traffic| async move {
+            handle_api_get_term_candidates(db, entities, traffic, query)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
 }
 
 pub fn api_get_terms(
     db: Arc<Database<String, RegexTerm>>,
+    stats: Arc<Stats>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    warp::path("terms")
+    warp::path!("terms")
         .and(warp::get())
+        .and(warp::query())
         .and(warp::any().map(move || db.clone()))
-        .and_then(move |db| async move {
-            handle_api_get_terms(db)
+        .and(warp::any().map(move || stats.clone()))
+        .and_then(move |query, db, stats| async move {
+            handle_api_get_terms(db, stats, query)
+                .await
+                .map(|(reply, total, next)| {
+                    let mut response = warp::reply::Response::new(reply.into());
+                    let headers = response.headers_mut();
+                    headers.insert("x-total-count", total.into());
+                    if let Some(next) = next {
+                        // Cursors are URL-safe base64, which is always a valid header value.
+                        headers.insert("x-next-cursor", next.parse().unwrap());
+                    }
+                    response
+                })
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_get_term_stats(
+    db: Arc<Database<String, RegexTerm>>,
+    stats: Arc<Stats>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("terms" / "stats")
+        .and(warp::get())
+        .and(warp::any().map(move || db.clone()))
+        .and(warp::any().map(move || stats.clone()))
+        .and_then(move |db, stats| async move {
+            handle_api_get_term_stats(db, stats)
                 .await
                 .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
         })
@@ -160,6 +721,7 @@ pub fn api_get_term(
         .and_then(move |id, db| async move {
             handle_api_get_term(db, id)
                 .await
+                .map(|(reply, etag)| warp::reply::with_header(reply, "etag", etag))
                 .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
         })
 }
@@ -174,6 +736,7 @@ pub fn api_post_term(
         .and_then(move |body, db| async move {
             handle_api_post_term(db, body)
                 .await
+                .map(|(reply, etag)| warp::reply::with_header(reply, "etag", etag))
                 .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
         })
 }
@@ -183,39 +746,397 @@ pub fn api_put_term(
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("term" / String)
         .and(warp::put())
+        .and(warp::header::optional::<String>("if-match"))
         .and(warp::body::json())
         .and(warp::any().map(move || db.clone()))
-        .and_then(move |id, body, db| async move {
-            handle_api_put_term(db, id, body)
+        .and_then(move |id, if_match, body, db| async move {
+            handle_api_put_term(db, id, if_match, body)
+                .await
+                .map(|(reply, etag)| warp::reply::with_header(reply, "etag", etag))
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_patch_term(
+    db: Arc<Database<String, RegexTerm>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("term" / String)
+        .and(warp::patch())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::body::json())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |id, if_match, body, db| async move {
+            handle_api_patch_term(db, id, if_match, body)
                 .await
+                .map(|(reply, etag)| warp::reply::with_header(reply, "etag", etag))
                 .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
         })
 }
 
 pub fn api_delete_term(
     db: Arc<Database<String, RegexTerm>>,
+    stats: Arc<Stats>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("term" / String)
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::any().map(move || db.clone()))
+        .and(warp::any().map(move || stats.clone()))
+        .and_then(move |id, if_match, db, stats| async move {
+            handle_api_delete_term(db, stats, id, if_match)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_get_entities(
+    db: Arc<Database<String, Entity>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("entities")
+        .and(warp::get())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |db| async move {
+            handle_api_get_entities(db)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_get_entity(
+    db: Arc<Database<String, Entity>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("entity" / String)
+        .and(warp::get())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |id, db| async move {
+            handle_api_get_entity(db, id)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_post_entity(
+    db: Arc<Database<String, Entity>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("entity")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |body, db| async move {
+            handle_api_post_entity(db, body)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_put_entity(
+    db: Arc<Database<String, Entity>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("entity" / String)
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |id, body, db| async move {
+            handle_api_put_entity(db, id, body)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_delete_entity(
+    db: Arc<Database<String, Entity>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("entity" / String)
         .and(warp::delete())
         .and(warp::any().map(move || db.clone()))
         .and_then(move |id, db| async move {
-            handle_api_delete_term(db, id)
+            handle_api_delete_entity(db, id)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_get_memory(
+    db: Arc<Database<String, MemoryEntry>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("memory")
+        .and(warp::get())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |db| async move {
+            handle_api_get_memory(db)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_post_memory(
+    db: Arc<Database<String, MemoryEntry>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("memory")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |body, db| async move {
+            handle_api_post_memory(db, body)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_delete_memory(
+    db: Arc<Database<String, MemoryEntry>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("memory" / String)
+        .and(warp::delete())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |id, db| async move {
+            handle_api_delete_memory(db, id)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_get_memory_tmx(
+    db: Arc<Database<String, MemoryEntry>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("memory" / "tmx")
+        .and(warp::get())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |db| async move {
+            handle_api_get_memory_tmx(db)
+                .await
+                .map(|reply| {
+                    warp::reply::with_header(reply, "content-type", "application/x-tmx+xml")
+                })
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_post_memory_tmx(
+    db: Arc<Database<String, MemoryEntry>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("memory" / "tmx")
+        .and(warp::post())
+        .and(warp::body::bytes())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |body, db| async move {
+            handle_api_post_memory_tmx(db, body)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_get_usage(
+    usage: Arc<Usage>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("usage")
+        .and(warp::get())
+        .and(warp::any().map(move || usage.clone()))
+        .and_then(move |usage| async move {
+            handle_api_get_usage(usage)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_get_metrics(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and_then(|| async move {
+            handle_api_get_metrics()
+                .await
+                .map(|reply| {
+                    warp::reply::with_header(reply, "content-type", "text/plain; version=0.0.4")
+                })
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+/// Record every request in the metrics, labelled by route rather than by path.
+pub fn log_metrics() -> warp::log::Log<impl Fn(warp::log::Info) + Copy> {
+    warp::log::custom(|info| {
+        metrics::observe_request(
+            info.method().as_str(),
+            info.path(),
+            info.status().as_u16(),
+            info.elapsed(),
+        )
+    })
+}
+
+pub fn api_get_feedback(
+    db: Arc<Database<String, Feedback>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("feedback")
+        .and(warp::get())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |db| async move {
+            handle_api_get_feedback(db)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_post_feedback(
+    db: Arc<Database<String, Feedback>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("feedback")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || db.clone()))
+        .and_then(move |body, db| async move {
+            handle_api_post_feedback(db, body)
+                .await
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_get_feedback_terms(
+    db: Arc<Database<String, Feedback>>,
+    terms: Arc<Database<String, RegexTerm>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("feedback" / "terms")
+        .and(warp::get())
+        .and(warp::query())
+        .and(warp::any().map(move || db.clone()))
+        .and(warp::any().map(move || terms.clone()))
+        .and_then(move |query, db, terms| async move {
+            handle_api_get_feedback_terms(db, terms, query)
                 .await
                 .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
         })
 }
 
 pub fn api_post_translate(
-    db: Arc<Database<String, RegexTerm>>,
+    pipeline: Arc<Pipeline>,
+    traffic: Option<Arc<Traffic>>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("translate")
         .and(warp::post())
         .and(warp::query())
         .and(warp::body::json())
-        .and(warp::any().map(move || db.clone()))
-        .and_then(move |query, body, db| async move {
-            handle_api_post_translate(db, query, body)
+        .and(warp::any().map(move || pipeline.clone()))
+        .and(warp::any().map(move || traffic.clone()))
+        .and_then(move |query, body, pipeline, traffic| async move {
+            handle_api_post_translate(pipeline, traffic, query, body)
                 .await
                 .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
         })
 }
+
+pub fn api_post_translate_subtitles(
+    pipeline: Arc<Pipeline>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("translate" / "subtitles")
+        .and(warp::post())
+        .and(warp::query())
+        .and(warp::body::bytes())
+        .and(warp::any().map(move || pipeline.clone()))
+        .and_then(move |query, body, pipeline| async move {
+            handle_api_post_translate_subtitles(pipeline, query, body)
+                .await
+                .map(|(reply, format)| {
+                    warp::reply::with_header(reply, "content-type", format.content_type())
+                })
+                .map_err(|err| warp::Rejection::from(crate::WarpError::from(err)))
+        })
+}
+
+pub fn api_chat(
+    pipeline: Arc<Pipeline>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("chat")
+        .and(warp::get())
+        .and(warp::query())
+        .and(warp::ws())
+        .and(warp::any().map(move || pipeline.clone()))
+        .and_then(
+            move |settings: chat::Settings, ws: warp::ws::Ws, pipeline| async move {
+                if crate::runtime()
+                    .translator(settings.target_lang())
+                    .is_none()
+                {
+                    let err = ApiError::UnsupportedLanguage(settings.target_lang().to_owned());
+                    return Err(warp::Rejection::from(crate::WarpError::from(err)));
+                }
+                Ok(ws.on_upgrade(move |socket| chat::serve(socket, pipeline, settings)))
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::FilterList;
+    use serde_json::json;
+
+    fn term(version: u64) -> RegexTerm {
+        serde_json::from_value(json!({
+            "input": "すいせい",
+            "output": "Suisei",
+            "version": version,
+        }))
+        .unwrap()
+    }
+
+    fn check(if_match: Option<&str>, version: u64) -> Option<ApiError> {
+        check_if_match(if_match, "1", &term(version))
+            .err()
+            .map(|err| err.downcast().unwrap())
+    }
+
+    #[test]
+    fn if_match_accepts_current_version() {
+        assert!(check(Some("\"3\""), 3).is_none());
+        assert!(check(Some("W/\"3\""), 3).is_none());
+        assert!(check(Some("\"2\", \"3\""), 3).is_none());
+        assert!(check(Some("*"), 3).is_none());
+    }
+
+    #[test]
+    fn if_match_rejects_other_versions() {
+        match check(Some("\"2\""), 3) {
+            Some(ApiError::Modified { id, current }) => {
+                assert_eq!(id, "1");
+                assert_eq!(current["version"], 3);
+            }
+            _ => panic!("expected a modified term"),
+        }
+        assert!(matches!(
+            check(None, 3),
+            Some(ApiError::PreconditionRequired)
+        ));
+    }
+
+    #[test]
+    fn merge_patch_follows_rfc_7396() {
+        let mut current = term(1);
+        current.translator = Some(FilterList::new(true, vec!["Google".to_owned()]));
+        current.comment = "nickname".to_owned();
+        let mut target = serde_json::to_value(&current).unwrap();
+        merge_patch(
+            &mut target,
+            json!({ "output": "Hoshimachi", "translator": { "list": ["DeepL"] }, "comment": null }),
+        );
+        assert_eq!(
+            target,
+            json!({
+                "input": "すいせい",
+                "output": "Hoshimachi",
+                "translator": { "exclude": true, "list": ["DeepL"] },
+                "version": 1,
+            })
+        );
+
+        let patched = parse_term(target.clone()).unwrap();
+        assert_eq!(patched.input.as_str(), "すいせい");
+        assert_eq!(patched.output, "Hoshimachi");
+        let translator = patched.translator.unwrap();
+        assert!(!translator.contains("DeepL") && translator.contains("Google"));
+        assert!(patched.comment.is_empty());
+
+        merge_patch(&mut target, json!(["not", "an", "object"]));
+        assert_eq!(target, json!(["not", "an", "object"]));
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use futures::stream::FuturesUnordered;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use warp::ws::{Message, WebSocket};
+
+use crate::error::{ApiError, ErrorMessage};
+use crate::pipeline::{Pipeline, Request};
+
+/// Settings of a connection, given in the query string and changed by `settings` messages.
+#[derive(Deserialize)]
+pub struct Settings {
+    #[serde(rename = "to")]
+    target_lang: String,
+    /// Contexts the whole chat belongs to, e.g. the member who is streaming.
+    #[serde(default, deserialize_with = "comma_separated")]
+    contexts: Vec<String>,
+}
+
+impl Settings {
+    pub fn target_lang(&self) -> &str {
+        &self.target_lang
+    }
+}
+
+fn comma_separated<'de, D>(der: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let str = String::deserialize(der)?;
+    Ok(str
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Incoming {
+    Settings {
+        #[serde(rename = "to")]
+        target_lang: Option<String>,
+        contexts: Option<Vec<String>>,
+    },
+    Message(ChatMessage),
+}
+
+#[derive(Deserialize, Clone)]
+struct ChatMessage {
+    /// Chosen by the client to match translations to messages.
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    author: Option<String>,
+    text: String,
+    /// Context of this message in addition to those of the connection, e.g. the membership of
+    /// the author.
+    #[serde(default)]
+    context: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Outgoing<'a> {
+    Translation {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<&'a str>,
+        translation: &'a str,
+        /// Whether the translation is reused from an identical message.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        repeated: bool,
+    },
+    /// The message is dropped without translation, as the connection has too many waiting.
+    Dropped {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<&'a str>,
+    },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<&'a str>,
+        #[serde(flatten)]
+        error: ErrorMessage,
+    },
+}
+
+impl Outgoing<'_> {
+    fn to_message(&self) -> Message {
+        Message::text(serde_json::to_string(self).unwrap())
+    }
+}
+
+/// Identity of a message for deduplication, so repeated spam is translated only once.
+fn dedup_key(message: &ChatMessage) -> String {
+    let text = message
+        .text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("{}\n{}", message.context.as_deref().unwrap_or(""), text)
+}
+
+/// Translations remembered for repeated messages, evicting the oldest first.
+struct Cache {
+    capacity: usize,
+    order: VecDeque<String>,
+    map: HashMap<String, String>,
+}
+
+impl Cache {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.map.get(key).map(String::as_str)
+    }
+
+    fn insert(&mut self, key: String, translation: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.map.insert(key.clone(), translation).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > self.capacity {
+            if let Some(key) = self.order.pop_front() {
+                self.map.remove(&key);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.order.clear();
+        self.map.clear();
+    }
+}
+
+/// Translate messages of a live chat connection as they arrive.
+///
+/// At most `chat.concurrency` messages are translated at once, and at most `chat.queue` wait for
+/// translation; the oldest waiting messages are dropped when the chat is busier than that, so
+/// a flood of messages cannot exhaust the quota of the provider.
+pub async fn serve(socket: WebSocket, pipeline: Arc<Pipeline>, mut settings: Settings) {
+    let config = &crate::runtime().config.chat;
+    let (concurrency, queue_limit) = (config.concurrency.max(1), config.queue);
+    let mut cache = Cache {
+        capacity: config.cache,
+        order: VecDeque::new(),
+        map: HashMap::new(),
+    };
+
+    let (mut tx, mut rx) = socket.split();
+    let mut queue: VecDeque<((u64, String), ChatMessage)> = VecDeque::new();
+    // Ids of the messages waiting for each queued or running translation, so that repeated
+    // messages share a single translation. Keyed by the generation of settings the message
+    // arrived with, so messages never share a translation made under other settings.
+    let mut waiting: HashMap<(u64, String), Vec<Option<String>>> = HashMap::new();
+    let mut in_flight = FuturesUnordered::new();
+    // Incremented on every change of settings, so earlier translations are not cached.
+    let mut generation: u64 = 0;
+
+    loop {
+        while in_flight.len() < concurrency {
+            let (key, message) = match queue.pop_front() {
+                Some(v) => v,
+                None => break,
+            };
+            let pipeline = pipeline.clone();
+            let target_lang = settings.target_lang.clone();
+            let mut contexts = settings.contexts.clone();
+            contexts.extend(message.context.clone());
+            let started = generation;
+            in_flight.push(async move {
+                let runtime = crate::runtime();
+                let result = pipeline
+                    .translate(
+                        &runtime,
+                        Request {
+                            target_lang: &target_lang,
+                            text: &message.text,
+                            author: message.author.as_deref(),
+                            contexts: &contexts,
+                            ..Default::default()
+                        },
+                    )
+                    .await;
+                (key, started, result)
+            });
+        }
+
+        let replies = tokio::select! {
+            incoming = rx.next() => {
+                let incoming = match incoming {
+                    Some(Ok(v)) => v,
+                    Some(Err(err)) => {
+                        log::info!("Chat connection closed: {}", err);
+                        break;
+                    }
+                    None => break,
+                };
+                if incoming.is_close() {
+                    break;
+                }
+                let text = match incoming.to_str() {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+
+                match serde_json::from_str(text) {
+                    Ok(Incoming::Settings { target_lang, contexts }) => {
+                        if let Some(target_lang) = target_lang {
+                            if crate::runtime().translator(&target_lang).is_none() {
+                                let error = ApiError::UnsupportedLanguage(target_lang).to_message();
+                                let reply = Outgoing::Error { id: None, error }.to_message();
+                                if tx.send(reply).await.is_err() {
+                                    return;
+                                }
+                                continue;
+                            }
+                            settings.target_lang = target_lang;
+                        }
+                        if let Some(contexts) = contexts {
+                            settings.contexts = contexts;
+                        }
+                        // Translations made under the previous settings no longer apply.
+                        cache.clear();
+                        generation += 1;
+                        continue;
+                    }
+                    Ok(Incoming::Message(message)) => {
+                        let key = (generation, dedup_key(&message));
+                        if let Some(translation) = cache.get(&key.1) {
+                            let reply = Outgoing::Translation {
+                                id: message.id.as_deref(),
+                                translation,
+                                repeated: true,
+                            };
+                            vec![reply.to_message()]
+                        } else if let Some(ids) = waiting.get_mut(&key) {
+                            ids.push(message.id);
+                            continue;
+                        } else {
+                            waiting.insert(key.clone(), vec![message.id.clone()]);
+                            queue.push_back((key, message));
+                            let mut replies = Vec::new();
+                            while queue.len() > queue_limit {
+                                let (key, _) = queue.pop_front().unwrap();
+                                for id in waiting.remove(&key).unwrap_or_default() {
+                                    replies.push(Outgoing::Dropped { id: id.as_deref() }.to_message());
+                                }
+                            }
+                            replies
+                        }
+                    }
+                    Err(err) => {
+                        let error = ApiError::InvalidBody(err.to_string()).to_message();
+                        vec![Outgoing::Error { id: None, error }.to_message()]
+                    }
+                }
+            }
+            Some((key, started, result)) = in_flight.next(), if !in_flight.is_empty() => {
+                let ids = waiting.remove(&key).unwrap_or_default();
+                match result {
+                    Ok(translation) => {
+                        let replies = ids
+                            .iter()
+                            .enumerate()
+                            .map(|(i, id)| {
+                                Outgoing::Translation {
+                                    id: id.as_deref(),
+                                    translation: &translation.translation,
+                                    repeated: i > 0,
+                                }
+                                .to_message()
+                            })
+                            .collect();
+                        if started == generation {
+                            cache.insert(key.1, translation.translation);
+                        }
+                        replies
+                    }
+                    Err(err) => {
+                        let (_, error) = ErrorMessage::describe(&err);
+                        ids.iter()
+                            .map(|id| {
+                                let error = error.clone();
+                                Outgoing::Error { id: id.as_deref(), error }.to_message()
+                            })
+                            .collect()
+                    }
+                }
+            }
+        };
+
+        for reply in replies {
+            if tx.send(reply).await.is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+pub mod terms;
+pub mod translate;
+
+#[derive(Parser)]
+#[command(version, about = "Auto Virtual YouTuber Translator")]
+pub struct Cli {
+    /// Path of the configuration file.
+    #[arg(
+        long,
+        short,
+        global = true,
+        env = "AYT_CONFIG",
+        default_value = "config.toml"
+    )]
+    pub config: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the API and the frontend. This is the default.
+    Serve,
+    /// Manage the term dictionary.
+    ///
+    /// The dictionary is read and written directly, so changes made while the server is running
+    /// are overwritten by the server.
+    Terms {
+        /// Path of the dictionary, instead of the one in the configuration file.
+        #[arg(long, global = true)]
+        database: Option<PathBuf>,
+        /// Path of the statistics of term matches, instead of the one in the configuration file.
+        #[arg(long, global = true)]
+        term_stats: Option<PathBuf>,
+        #[command(subcommand)]
+        command: terms::Command,
+    },
+    /// Translate texts from files or standard input.
+    ///
+    /// Output preserves the order of the input. Blank lines, and records without the field to
+    /// translate, are written out unchanged.
+    Translate(translate::TranslateArgs),
+}
@@ -0,0 +1,271 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Subcommand;
+use fancy_regex::Regex;
+
+use crate::db::{Database, Keyed};
+use crate::schema::{RegexTerm, TermType};
+use crate::stats::Stats;
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// List terms, ordered by id.
+    List {
+        /// Only list terms that apply to this target language.
+        #[arg(long)]
+        lang: Option<String>,
+        /// Print terms as JSON lines instead of a table.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add a term and print its id.
+    Add {
+        /// Regular expression to match in the source text.
+        input: String,
+        output: String,
+        /// Target language. The term applies to all languages if unset.
+        #[arg(long)]
+        lang: Option<String>,
+        #[arg(long = "type", value_parser = parse_type, default_value = "transform")]
+        ty: TermType,
+        #[arg(long, default_value_t = 0)]
+        priority: u32,
+        #[arg(long, default_value = "")]
+        comment: String,
+    },
+    /// Remove terms by id.
+    Rm {
+        #[arg(required = true)]
+        ids: Vec<String>,
+    },
+    /// Report terms that are likely mistakes. Exits with failure if any is found.
+    Lint,
+    /// Import terms from a JSON array or JSON lines, as produced by `export`.
+    ///
+    /// Terms with an `_id` replace the term with that id, others are added with a new id.
+    Import {
+        /// File to read, or `-` for standard input.
+        file: PathBuf,
+        /// Remove all existing terms first.
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Export all terms as a JSON array.
+    Export {
+        /// File to write. Standard output if unset.
+        file: Option<PathBuf>,
+    },
+}
+
+fn parse_type(value: &str) -> Result<TermType, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_owned()))
+        .map_err(|_| "expected one of `preprocess`, `transform`, `postprocess`".to_owned())
+}
+
+/// Order ids numerically where possible, as they are allocated from 0 upwards.
+fn sort_ids<T>(terms: &mut [Keyed<String, T>]) {
+    terms.sort_by(|a, b| {
+        (a.key.parse::<u64>().ok(), &a.key).cmp(&(b.key.parse::<u64>().ok(), &b.key))
+    });
+}
+
+fn sorted_terms(db: &Database<String, RegexTerm>) -> anyhow::Result<Vec<Keyed<String, RegexTerm>>> {
+    let mut terms: Vec<_> = db
+        .iter()?
+        .map(|x| Keyed {
+            key: x.key.clone(),
+            value: x.value.clone(),
+        })
+        .collect();
+    sort_ids(&mut terms);
+    Ok(terms)
+}
+
+/// Insert a term under the first free id.
+fn insert(map: &mut HashMap<String, RegexTerm>, term: RegexTerm) -> String {
+    for i in 0.. {
+        let key = i.to_string();
+        if !map.contains_key(&key) {
+            map.insert(key.clone(), term);
+            return key;
+        }
+    }
+    unreachable!()
+}
+
+fn lint(terms: &[Keyed<String, RegexTerm>]) -> anyhow::Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let mut seen: HashMap<(&str, Option<&str>, TermType), &str> = HashMap::new();
+    for term in terms {
+        let (id, term) = (&term.key, &term.value);
+        if term.input.is_match("")? {
+            problems.push(format!("{}: input `{}` matches empty text", id, term.input));
+        }
+        if regex::escape(&term.output) == term.input.as_str() {
+            problems.push(format!("{}: output is the same as input", id));
+        }
+        let identity = (term.input.as_str(), term.target_lang.as_deref(), term.ty);
+        match seen.get(&identity) {
+            Some(first) => problems.push(format!("{}: duplicate of {}", id, first)),
+            None => {
+                seen.insert(identity, id);
+            }
+        }
+    }
+    Ok(problems)
+}
+
+fn read_import(file: &Path) -> anyhow::Result<Vec<Keyed<Option<String>, RegexTerm>>> {
+    let mut text = String::new();
+    if file == Path::new("-") {
+        std::io::stdin().read_to_string(&mut text)?;
+    } else {
+        text = std::fs::read_to_string(file)
+            .with_context(|| format!("Cannot read {}", file.display()))?;
+    }
+
+    if text.trim_start().starts_with('[') {
+        return serde_json::from_str(&text).context("Cannot parse terms");
+    }
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).with_context(|| format!("Cannot parse line {}", i + 1))
+        })
+        .collect()
+}
+
+pub fn run(database: &Path, term_stats: &Path, command: Command) -> anyhow::Result<()> {
+    let db = Database::<String, RegexTerm>::open(database)?;
+    // Statistics of removed terms are forgotten, so they are not inherited by terms reusing ids.
+    let forget = |ids: &[String]| -> anyhow::Result<()> {
+        let stats = Stats::open(term_stats)?;
+        for id in ids {
+            stats.remove(id)?;
+        }
+        Ok(())
+    };
+
+    match command {
+        Command::List { lang, json } => {
+            let mut out = std::io::stdout().lock();
+            for term in sorted_terms(&db)? {
+                if let (Some(lang), Some(target_lang)) = (&lang, &term.value.target_lang) {
+                    if lang != target_lang {
+                        continue;
+                    }
+                }
+                if json {
+                    serde_json::to_writer(&mut out, &term)?;
+                    writeln!(out)?;
+                } else {
+                    writeln!(
+                        out,
+                        "{}\t{}\t{}\t{}\t{}",
+                        term.key,
+                        term.value.ty.as_str(),
+                        term.value.target_lang.as_deref().unwrap_or("*"),
+                        term.value.input,
+                        term.value.output
+                    )?;
+                }
+            }
+        }
+        Command::Add {
+            input,
+            output,
+            lang,
+            ty,
+            priority,
+            comment,
+        } => {
+            let input = Regex::new(&input).context("Invalid input")?;
+            let mut term = RegexTerm {
+                input,
+                output,
+                target_lang: lang,
+                translator: None,
+                priority,
+                context: None,
+                ty,
+                comment,
+                version: 0,
+                updated_at: 0,
+                id: None,
+            };
+            term.touch(None);
+            let key = db.db.write(|map| insert(map, term))?;
+            db.save()?;
+            println!("{}", key);
+        }
+        Command::Rm { ids } => {
+            db.db.write(|map| {
+                if let Some(id) = ids.iter().find(|id| !map.contains_key(*id)) {
+                    anyhow::bail!("Term {} not found", id);
+                }
+                for id in &ids {
+                    map.remove(id);
+                }
+                Ok(())
+            })??;
+            db.save()?;
+            forget(&ids)?;
+        }
+        Command::Lint => {
+            let problems = lint(&sorted_terms(&db)?)?;
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                anyhow::bail!("Found {} problems", problems.len());
+            }
+        }
+        Command::Import { file, replace } => {
+            let terms = read_import(&file)?;
+            let count = terms.len();
+            let imported: HashSet<_> = terms.iter().filter_map(|x| x.key.clone()).collect();
+            let removed = db.db.write(|map| {
+                // Versions continue from replaced terms, so editors notice the change.
+                let previous = match replace {
+                    true => std::mem::take(map),
+                    false => HashMap::new(),
+                };
+                for mut term in terms {
+                    match term.key {
+                        Some(key) => {
+                            term.value.touch(map.get(&key).or(previous.get(&key)));
+                            map.insert(key, term.value);
+                        }
+                        None => {
+                            term.value.touch(None);
+                            insert(map, term.value);
+                        }
+                    }
+                }
+                // Replaced terms that are not imported again under their id are removed, even if
+                // their id is reused by a new term.
+                previous
+                    .into_keys()
+                    .filter(|id| !imported.contains(id))
+                    .collect::<Vec<_>>()
+            })?;
+            db.save()?;
+            forget(&removed)?;
+            eprintln!("Imported {} terms", count);
+        }
+        Command::Export { file } => {
+            let terms = sorted_terms(&db)?;
+            let json = serde_json::to_string_pretty(&terms)?;
+            match file {
+                Some(file) => std::fs::write(&file, json + "\n")
+                    .with_context(|| format!("Cannot write {}", file.display()))?,
+                None => println!("{}", json),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::db::Database;
+use crate::pipeline::{Pipeline, Request};
+use crate::runtime::Runtime;
+use crate::subtitle::{self, Subtitle};
+use crate::usage::Usage;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// Translate each line separately.
+    Lines,
+    /// Translate paragraphs separated by blank lines.
+    Paragraphs,
+    /// Translate a field of each JSON record.
+    Jsonl,
+    /// Translate the cues of a SRT, WebVTT or ASS file, detected from its content.
+    Subtitles,
+}
+
+#[derive(Args)]
+pub struct TranslateArgs {
+    /// Files to translate. Standard input is read if none is given, or for `-`.
+    files: Vec<PathBuf>,
+    /// Target language.
+    #[arg(long)]
+    to: String,
+    #[arg(long, value_enum, default_value = "lines")]
+    format: Format,
+    /// Field of JSON records holding the text to translate.
+    #[arg(long, default_value = "text")]
+    field: String,
+    /// Field of JSON records to write the translation to.
+    #[arg(long, default_value = "translation")]
+    output_field: String,
+    /// Handle of the author of the texts, used to infer their context.
+    #[arg(long)]
+    author: Option<String>,
+    /// Number of texts translated at the same time.
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+    /// Number of neighboring subtitle cues translated together. Batches merged into fewer lines
+    /// by the translator are sent again cue by cue, so use 1 for translators that often do.
+    #[arg(long, default_value_t = 20)]
+    batch: usize,
+    /// Record finished translations in this file, and reuse them when run again.
+    #[arg(long)]
+    progress: Option<PathBuf>,
+    /// File to write. Standard output if unset.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+/// A unit of input, written out in the same order as it is read.
+enum Item {
+    /// Written out unchanged, such as blank lines.
+    Verbatim(String),
+    Text(String),
+    Record(serde_json::Map<String, serde_json::Value>, String),
+}
+
+impl Item {
+    fn source(&self) -> Option<&str> {
+        match self {
+            Item::Verbatim(_) => None,
+            Item::Text(text) | Item::Record(_, text) => Some(text),
+        }
+    }
+}
+
+/// A finished translation in the progress file.
+#[derive(Serialize, Deserialize)]
+struct Progress {
+    index: usize,
+    source: String,
+    translation: String,
+}
+
+fn read_input(files: &[PathBuf]) -> anyhow::Result<Vec<String>> {
+    let mut texts = Vec::new();
+    if files.is_empty() {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        texts.push(text);
+    }
+    for file in files {
+        let mut text = String::new();
+        if file == Path::new("-") {
+            std::io::stdin().read_to_string(&mut text)?;
+        } else {
+            text = std::fs::read_to_string(file)
+                .with_context(|| format!("Cannot read {}", file.display()))?;
+        }
+        texts.push(text);
+    }
+    Ok(texts)
+}
+
+fn split(text: &str, args: &TranslateArgs) -> anyhow::Result<Vec<Item>> {
+    let mut items = Vec::new();
+    match args.format {
+        Format::Lines => {
+            for line in text.lines() {
+                items.push(match line.trim().is_empty() {
+                    true => Item::Verbatim(line.to_owned()),
+                    false => Item::Text(line.to_owned()),
+                });
+            }
+        }
+        Format::Paragraphs => {
+            let mut paragraph: Vec<&str> = Vec::new();
+            for line in text.lines() {
+                if !line.trim().is_empty() {
+                    paragraph.push(line);
+                    continue;
+                }
+                if !paragraph.is_empty() {
+                    items.push(Item::Text(paragraph.join("\n")));
+                    paragraph.clear();
+                }
+                items.push(Item::Verbatim(line.to_owned()));
+            }
+            if !paragraph.is_empty() {
+                items.push(Item::Text(paragraph.join("\n")));
+            }
+        }
+        Format::Subtitles => unreachable!("subtitles are translated as a whole"),
+        Format::Jsonl => {
+            for (i, line) in text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    items.push(Item::Verbatim(line.to_owned()));
+                    continue;
+                }
+                let record: serde_json::Map<String, serde_json::Value> = serde_json::from_str(line)
+                    .with_context(|| format!("Cannot parse line {}", i + 1))?;
+                // Records without the field are passed through.
+                match record.get(&args.field).and_then(|x| x.as_str()) {
+                    Some(text) => {
+                        let text = text.to_owned();
+                        items.push(Item::Record(record, text));
+                    }
+                    None => items.push(Item::Verbatim(line.to_owned())),
+                }
+            }
+        }
+    }
+    Ok(items)
+}
+
+fn load_progress(path: &Path) -> anyhow::Result<HashMap<usize, Progress>> {
+    let file = match File::open(path) {
+        Ok(v) => v,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err).with_context(|| format!("Cannot read {}", path.display())),
+    };
+    let mut progress = HashMap::new();
+    for line in BufReader::new(file).lines() {
+        // A partially written last line is left over if the previous run was killed.
+        if let Ok(entry) = serde_json::from_str::<Progress>(&line?) {
+            progress.insert(entry.index, entry);
+        }
+    }
+    Ok(progress)
+}
+
+pub async fn run(config: Config, args: TranslateArgs) -> anyhow::Result<()> {
+    let pipeline = Pipeline {
+        terms: Arc::new(Database::open(&config.database)?),
+        entities: Arc::new(Database::open(&config.entities)?),
+        memory: Arc::new(Database::open(&config.memory)?),
+        // The server may be running on the same statistics and usage, which would be overwritten.
+        stats: None,
+    };
+    // Budgets still apply from the saved usage, but characters translated here are not counted.
+    let usage = Arc::new(Usage::load(&config.usage)?);
+    let runtime = Runtime::new(config, &usage)?;
+    if runtime.translator(&args.to).is_none() {
+        anyhow::bail!("Unsupported language {}", args.to);
+    }
+
+    translate(&pipeline, &runtime, args).await
+}
+
+async fn translate(
+    pipeline: &Pipeline,
+    runtime: &Runtime,
+    args: TranslateArgs,
+) -> anyhow::Result<()> {
+    let texts = read_input(&args.files)?;
+    if let Format::Subtitles = args.format {
+        if args.progress.is_some() {
+            anyhow::bail!("Progress is not recorded for subtitles");
+        }
+        let text = match <[_; 1]>::try_from(texts) {
+            Ok([text]) => text,
+            Err(_) => anyhow::bail!("Subtitles can only be translated one file at a time"),
+        };
+        let mut subtitle = Subtitle::parse(subtitle::Format::detect(&text), &text);
+        subtitle
+            .translate(
+                pipeline,
+                runtime,
+                &args.to,
+                args.author.as_deref(),
+                args.batch,
+            )
+            .await?;
+        match &args.output {
+            Some(path) => std::fs::write(path, subtitle.render())
+                .with_context(|| format!("Cannot write {}", path.display()))?,
+            None => print!("{}", subtitle.render()),
+        }
+        return Ok(());
+    }
+
+    let mut items = Vec::new();
+    for text in texts {
+        items.extend(split(&text, &args)?);
+    }
+
+    let mut done = match &args.progress {
+        Some(path) => load_progress(path)?,
+        None => HashMap::new(),
+    };
+    let mut progress = match &args.progress {
+        Some(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Cannot write {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let mut output: BufWriter<Box<dyn Write>> = BufWriter::new(match &args.output {
+        Some(path) => Box::new(
+            File::create(path).with_context(|| format!("Cannot write {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    });
+
+    // Translations finished by a previous run are only reused if the input is unchanged.
+    let reused: Vec<_> = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let entry = done.remove(&i)?;
+            (item.source() == Some(&*entry.source)).then_some(entry.translation)
+        })
+        .collect();
+    let total = items.iter().filter(|x| x.source().is_some()).count();
+    let resumed = reused.iter().filter(|x| x.is_some()).count();
+    if resumed > 0 {
+        eprintln!("Resuming with {} of {} texts translated", resumed, total);
+    }
+
+    let args = &args;
+    let mut results = futures::stream::iter(items.into_iter().zip(reused).enumerate())
+        .map(|(index, (item, reused))| async move {
+            // Whether the translation is new and has to be recorded in the progress file.
+            let (translation, fresh) = match (item.source(), reused) {
+                (Some(_), Some(translation)) => (translation, false),
+                (Some(source), None) => {
+                    let translation = pipeline
+                        .translate(
+                            runtime,
+                            Request {
+                                target_lang: &args.to,
+                                text: source,
+                                author: args.author.as_deref(),
+                                ..Default::default()
+                            },
+                        )
+                        .await
+                        .with_context(|| format!("Cannot translate text {}", index + 1))?;
+                    (translation.translation, true)
+                }
+                (None, _) => (String::new(), false),
+            };
+            anyhow::Ok((index, item, translation, fresh))
+        })
+        .buffered(args.concurrency.max(1));
+
+    while let Some(result) = results.next().await {
+        let (index, item, translation, fresh) = result?;
+        if let (Some(progress), true) = (&mut progress, fresh) {
+            let entry = Progress {
+                index,
+                source: item.source().unwrap_or_default().to_owned(),
+                translation: translation.clone(),
+            };
+            serde_json::to_writer(&mut *progress, &entry)?;
+            writeln!(progress)?;
+            progress.flush()?;
+        }
+
+        match item {
+            Item::Verbatim(line) => writeln!(output, "{}", line)?,
+            Item::Text(_) => writeln!(output, "{}", translation)?,
+            Item::Record(mut record, _) => {
+                record.insert(args.output_field.clone(), translation.into());
+                serde_json::to_writer(&mut output, &record)?;
+                writeln!(output)?;
+            }
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
@@ -0,0 +1,323 @@
+//! A subset of the [DeepL API](https://developers.deepl.com/docs): `/v2/translate`, `/v2/usage`
+//! and `/v2/languages`.
+//!
+//! Clients authenticate with the keys in `[clients]` of the configuration, and their usage is
+//! recorded under `client:<name>`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::Filter;
+
+use super::{detect_language, html_protected, Error};
+use crate::config::ClientConfig;
+use crate::error::ApiError;
+use crate::pipeline::{Pipeline, Request};
+use crate::runtime::{Runtime, SOURCE_LANG, TARGET_LANGS};
+use crate::usage::Usage;
+
+#[derive(Deserialize, Default)]
+struct TranslateParams {
+    #[serde(default)]
+    text: Vec<String>,
+    #[serde(default)]
+    target_lang: String,
+    #[serde(default)]
+    source_lang: Option<String>,
+    /// `xml` or `html` to keep tags out of translation.
+    #[serde(default)]
+    tag_handling: Option<String>,
+    /// Deprecated by DeepL in favor of the `Authorization` header, but still used by clients.
+    #[serde(default)]
+    auth_key: Option<String>,
+}
+
+impl TranslateParams {
+    /// Parse a JSON or form body. Forms repeat `text` for each text.
+    fn parse(content_type: Option<&str>, body: &[u8]) -> Result<Self, Error> {
+        if content_type.is_some_and(|x| x.starts_with("application/json")) {
+            return serde_json::from_slice(body)
+                .map_err(|err| Error(StatusCode::BAD_REQUEST, err.to_string()));
+        }
+        let mut params = Self::default();
+        for (key, value) in url::form_urlencoded::parse(body) {
+            match &*key {
+                "text" => params.text.push(value.into_owned()),
+                "target_lang" => params.target_lang = value.into_owned(),
+                "source_lang" => params.source_lang = Some(value.into_owned()),
+                "tag_handling" => params.tag_handling = Some(value.into_owned()),
+                "auth_key" => params.auth_key = Some(value.into_owned()),
+                _ => (),
+            }
+        }
+        Ok(params)
+    }
+}
+
+#[derive(Serialize)]
+struct Translation {
+    /// Like DeepL, this is `source_lang` if given, which can only be Japanese. Translators don't
+    /// report the language they detected, so it is otherwise guessed from the script of the text.
+    detected_source_language: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct TranslateResponse {
+    translations: Vec<Translation>,
+}
+
+#[derive(Serialize)]
+struct UsageResponse {
+    character_count: u64,
+    /// DeepL reports a huge limit for unlimited keys, which clients expect to be present.
+    character_limit: u64,
+}
+
+#[derive(Serialize)]
+struct Language {
+    language: &'static str,
+    name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    supports_formality: Option<bool>,
+}
+
+fn usage_key(client: &ClientConfig) -> String {
+    format!("client:{}", client.name)
+}
+
+/// Find the client of an auth key, given in the `Authorization` header or as a parameter.
+fn authenticate<'a>(
+    runtime: &'a Runtime,
+    header: Option<&str>,
+    param: Option<&str>,
+) -> Result<&'a ClientConfig, Error> {
+    let key = header
+        .map(|x| x.trim_start_matches("DeepL-Auth-Key").trim())
+        .or(param);
+    key.and_then(|key| runtime.config.clients.get(key))
+        .ok_or_else(|| Error(StatusCode::FORBIDDEN, "Authorization failed".to_owned()))
+}
+
+/// Target language of the dictionary for a DeepL language code such as `EN-US`.
+fn target_lang(code: &str) -> String {
+    let lang = code.split('-').next().unwrap_or_default();
+    lang.to_lowercase()
+}
+
+async fn handle_translate(
+    pipeline: Arc<Pipeline>,
+    usage: Arc<Usage>,
+    authorization: Option<String>,
+    content_type: Option<String>,
+    body: warp::hyper::body::Bytes,
+) -> Result<TranslateResponse, Error> {
+    let params = TranslateParams::parse(content_type.as_deref(), &body)?;
+    let runtime = crate::runtime();
+    let client = authenticate(
+        &runtime,
+        authorization.as_deref(),
+        params.auth_key.as_deref(),
+    )?;
+
+    let bad_request = |message: &str| Error(StatusCode::BAD_REQUEST, message.to_owned());
+    if params.text.is_empty() {
+        return Err(bad_request("Parameter 'text' not specified."));
+    }
+    if params.target_lang.is_empty() {
+        return Err(bad_request("Value for 'target_lang' not specified."));
+    }
+    let target = target_lang(&params.target_lang);
+    if runtime.translator(&target).is_none() {
+        return Err(anyhow::Error::from(ApiError::UnsupportedLanguage(params.target_lang)).into());
+    }
+    // Translators are only given Japanese, so any other source would be translated as such.
+    if params
+        .source_lang
+        .as_deref()
+        .is_some_and(|x| !x.eq_ignore_ascii_case(SOURCE_LANG))
+    {
+        return Err(bad_request("Value for 'source_lang' not supported."));
+    }
+
+    let key = usage_key(client);
+    let characters: u64 = params.text.iter().map(|x| x.chars().count() as u64).sum();
+    if !usage.reserve(&key, characters, client.monthly)? {
+        return Err(Error(
+            StatusCode::from_u16(456).unwrap(),
+            "Quota exceeded".to_owned(),
+        ));
+    }
+
+    let protected = match params.tag_handling.as_deref() {
+        Some("xml" | "html") => html_protected(),
+        _ => Vec::new(),
+    };
+    let mut translations = Vec::with_capacity(params.text.len());
+    for text in &params.text {
+        let result = pipeline
+            .translate(
+                &runtime,
+                Request {
+                    target_lang: &target,
+                    text,
+                    protected: &protected,
+                    ..Default::default()
+                },
+            )
+            .await;
+        let translation = match result {
+            Ok(v) => v,
+            Err(err) => {
+                usage.release(&key, characters)?;
+                return Err(err.into());
+            }
+        };
+        translations.push(Translation {
+            detected_source_language: match &params.source_lang {
+                Some(source) => source.to_uppercase(),
+                None => detect_language(text).0.to_uppercase(),
+            },
+            text: translation.translation,
+        });
+    }
+    Ok(TranslateResponse { translations })
+}
+
+fn handle_usage(
+    usage: &Usage,
+    authorization: Option<String>,
+    query: HashMap<String, String>,
+) -> Result<UsageResponse, Error> {
+    let runtime = crate::runtime();
+    let client = authenticate(
+        &runtime,
+        authorization.as_deref(),
+        query.get("auth_key").map(String::as_str),
+    )?;
+    Ok(UsageResponse {
+        character_count: usage.get(&usage_key(client))?,
+        character_limit: client.monthly.unwrap_or(1_000_000_000_000),
+    })
+}
+
+fn handle_languages(
+    authorization: Option<String>,
+    query: HashMap<String, String>,
+) -> Result<Vec<Language>, Error> {
+    let runtime = crate::runtime();
+    authenticate(
+        &runtime,
+        authorization.as_deref(),
+        query.get("auth_key").map(String::as_str),
+    )?;
+
+    let language = |language, name, supports_formality| Language {
+        language,
+        name,
+        supports_formality,
+    };
+    Ok(match query.get("type").map(String::as_str) {
+        Some("target") => [
+            language("EN-GB", "English (British)", Some(false)),
+            language("EN-US", "English (American)", Some(false)),
+            language("ZH", "Chinese (simplified)", Some(false)),
+        ]
+        .into_iter()
+        .filter(|x| TARGET_LANGS.contains(&&*target_lang(x.language)))
+        .collect(),
+        _ => vec![language("JA", "Japanese", None)],
+    })
+}
+
+async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    let (status, message) = match err.find::<Error>() {
+        Some(Error(status, message)) => (*status, message.clone()),
+        // Anything else, such as an unknown path, is left to the other routes.
+        None => return Err(err),
+    };
+    let json = warp::reply::json(&serde_json::json!({ "message": message }));
+    Ok(warp::reply::with_status(json, status))
+}
+
+/// Parameters given in the query string or, as clients do with `POST`, in a form body.
+fn params() -> impl Filter<Extract = (HashMap<String, String>,), Error = warp::Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .and(warp::body::bytes())
+        .map(
+            |mut query: HashMap<String, String>, body: warp::hyper::body::Bytes| {
+                query.extend(url::form_urlencoded::parse(&body).into_owned());
+                query
+            },
+        )
+}
+
+fn reply<T: Serialize>(result: Result<T, Error>) -> Result<warp::reply::Json, warp::Rejection> {
+    result
+        .map(|x| warp::reply::json(&x))
+        .map_err(warp::reject::custom)
+}
+
+pub fn routes(
+    pipeline: Arc<Pipeline>,
+    usage: Arc<Usage>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let authorization = || warp::header::optional::<String>("authorization");
+
+    let translate_usage = usage.clone();
+    let translate = warp::path!("v2" / "translate")
+        .and(warp::post())
+        .and(authorization())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::bytes())
+        .and(warp::any().map(move || (pipeline.clone(), translate_usage.clone())))
+        .and_then(
+            |authorization, content_type, body, (pipeline, usage)| async move {
+                reply(handle_translate(pipeline, usage, authorization, content_type, body).await)
+            },
+        );
+    let usage = warp::path!("v2" / "usage")
+        .and(warp::get().or(warp::post()).unify())
+        .and(authorization())
+        .and(params())
+        .and(warp::any().map(move || usage.clone()))
+        .and_then(|authorization, query, usage: Arc<Usage>| async move {
+            reply(handle_usage(&usage, authorization, query))
+        });
+    let languages = warp::path!("v2" / "languages")
+        .and(warp::get().or(warp::post()).unify())
+        .and(authorization())
+        .and(params())
+        .and_then(
+            |authorization, query| async move { reply(handle_languages(authorization, query)) },
+        );
+
+    translate.or(usage).or(languages).recover(handle_rejection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_params_from_query_and_form() {
+        let found = warp::test::request()
+            .method("POST")
+            .path("/v2/languages?type=target")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body("auth_key=secret%3Afx")
+            .filter(&params())
+            .await
+            .unwrap();
+        assert_eq!(found["type"], "target");
+        assert_eq!(found["auth_key"], "secret:fx");
+
+        let found = warp::test::request()
+            .path("/v2/usage?auth_key=secret")
+            .filter(&params())
+            .await
+            .unwrap();
+        assert_eq!(found["auth_key"], "secret");
+    }
+}
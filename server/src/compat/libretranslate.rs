@@ -0,0 +1,191 @@
+//! The API of [LibreTranslate](https://libretranslate.com/docs): `POST /translate`,
+//! `GET /languages` and `POST /detect`.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::Filter;
+
+use super::{detect_language, html_protected, Error};
+use crate::error::ApiError;
+use crate::pipeline::{Pipeline, Request};
+use crate::runtime::{SOURCE_LANG, TARGET_LANGS};
+
+/// Either a single text or several, as accepted by `q`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Texts {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct TranslateBody {
+    q: Texts,
+    #[serde(default)]
+    source: Option<String>,
+    target: String,
+    /// `text` or `html`.
+    #[serde(default)]
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DetectedLanguage {
+    confidence: f64,
+    language: &'static str,
+}
+
+impl DetectedLanguage {
+    fn of(text: &str) -> Self {
+        let (language, confidence) = detect_language(text);
+        Self {
+            // LibreTranslate reports confidence in percent.
+            confidence: confidence * 100.0,
+            language,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Translated {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Detected {
+    One(DetectedLanguage),
+    Many(Vec<DetectedLanguage>),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TranslateResponse {
+    translated_text: Translated,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detected_language: Option<Detected>,
+}
+
+#[derive(Deserialize)]
+struct DetectBody {
+    q: String,
+}
+
+#[derive(Serialize)]
+struct Language {
+    code: &'static str,
+    name: &'static str,
+    targets: Vec<&'static str>,
+}
+
+async fn handle_translate(
+    pipeline: Arc<Pipeline>,
+    body: TranslateBody,
+) -> anyhow::Result<TranslateResponse> {
+    let runtime = crate::runtime();
+    if runtime.translator(&body.target).is_none() {
+        anyhow::bail!(ApiError::UnsupportedLanguage(body.target));
+    }
+    // Translators are only given Japanese, so any other source would be translated as such.
+    if let Some(source) = body.source.as_deref().filter(|x| *x != "auto") {
+        if source != SOURCE_LANG {
+            anyhow::bail!(ApiError::UnsupportedSourceLanguage(source.to_owned()));
+        }
+    }
+    let protected = match body.format.as_deref() {
+        Some("html") => html_protected(),
+        _ => Vec::new(),
+    };
+    let texts = match &body.q {
+        Texts::One(text) => std::slice::from_ref(text),
+        Texts::Many(texts) => texts.as_slice(),
+    };
+
+    let mut translations = Vec::with_capacity(texts.len());
+    for text in texts {
+        let translation = pipeline
+            .translate(
+                &runtime,
+                Request {
+                    target_lang: &body.target,
+                    text,
+                    protected: &protected,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        translations.push(translation.translation);
+    }
+
+    let auto = body.source.as_deref().is_none_or(|x| x == "auto");
+    Ok(match body.q {
+        Texts::One(text) => TranslateResponse {
+            translated_text: Translated::One(translations.swap_remove(0)),
+            detected_language: auto.then(|| Detected::One(DetectedLanguage::of(&text))),
+        },
+        Texts::Many(texts) => TranslateResponse {
+            translated_text: Translated::Many(translations),
+            detected_language: auto
+                .then(|| Detected::Many(texts.iter().map(|x| DetectedLanguage::of(x)).collect())),
+        },
+    })
+}
+
+fn languages() -> Vec<Language> {
+    vec![Language {
+        code: SOURCE_LANG,
+        name: "Japanese",
+        targets: TARGET_LANGS.to_vec(),
+    }]
+}
+
+/// Parameters are accepted both as JSON and as a form, like LibreTranslate does.
+fn json_or_form<T: serde::de::DeserializeOwned + Send>(
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    warp::body::json().or(warp::body::form()).unify()
+}
+
+async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    let Error(status, message) = if let Some(err) = err.find::<Error>() {
+        Error(err.0, err.1.clone())
+    } else if let Some(err) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        Error(StatusCode::BAD_REQUEST, err.to_string())
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        Error(
+            StatusCode::BAD_REQUEST,
+            "Expected a JSON or form body".to_owned(),
+        )
+    } else {
+        // Anything else, such as an unknown path, is left to the other routes.
+        return Err(err);
+    };
+    let json = warp::reply::json(&serde_json::json!({ "error": message }));
+    Ok(warp::reply::with_status(json, status))
+}
+
+pub fn routes(
+    pipeline: Arc<Pipeline>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let translate = warp::path!("translate")
+        .and(warp::post())
+        .and(json_or_form())
+        .and(warp::any().map(move || pipeline.clone()))
+        .and_then(|body, pipeline| async move {
+            handle_translate(pipeline, body)
+                .await
+                .map(|response| warp::reply::json(&response))
+                .map_err(|err| warp::reject::custom(Error::from(err)))
+        });
+    let languages = warp::path!("languages")
+        .and(warp::get())
+        .map(|| warp::reply::json(&languages()));
+    let detect = warp::path!("detect")
+        .and(warp::post())
+        .and(json_or_form())
+        .map(|body: DetectBody| warp::reply::json(&[DetectedLanguage::of(&body.q)]));
+
+    translate.or(languages).or(detect).recover(handle_rejection)
+}
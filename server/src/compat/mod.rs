@@ -0,0 +1,55 @@
+//! Endpoints speaking the protocols of other translation services, so existing clients can use
+//! the dictionary by changing their server URL.
+
+pub mod deepl;
+pub mod libretranslate;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use warp::http::StatusCode;
+
+use crate::error::ErrorMessage;
+
+/// Error with the status it is returned with, formatted by each protocol in its own way.
+#[derive(Debug)]
+struct Error(StatusCode, String);
+
+impl warp::reject::Reject for Error {}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        let (status, message) = ErrorMessage::describe(&err);
+        Error(status, message.message)
+    }
+}
+
+static HTML_TAG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^<>]*>").unwrap());
+static ENTITY_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"&(?:[a-zA-Z]+|#\d+|#x[0-9a-fA-F]+);").unwrap());
+
+/// Markup kept out of translation when a client sends HTML.
+fn html_protected() -> Vec<Regex> {
+    vec![HTML_TAG_REGEX.clone(), ENTITY_REGEX.clone()]
+}
+
+/// Guess the language of a text from its script, with a confidence between 0 and 1.
+///
+/// Only the languages the server works with are told apart: kana means Japanese, and Han
+/// characters without kana mean Chinese.
+fn detect_language(text: &str) -> (&'static str, f64) {
+    let kana = text
+        .chars()
+        .filter(|c| matches!(c, '\u{3040}'..='\u{30ff}'))
+        .count();
+    let han = text
+        .chars()
+        .filter(|c| matches!(c, '\u{4e00}'..='\u{9fff}'))
+        .count();
+    if kana > 0 {
+        ("ja", 0.9)
+    } else if han > 0 {
+        ("zh", 0.7)
+    } else {
+        ("en", 0.5)
+    }
+}
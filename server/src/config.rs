@@ -1,30 +1,303 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 
+use anyhow::Context;
 use serde::Deserialize;
 
-#[cfg(feature = "baidu")]
+#[cfg(feature = "http")]
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+#[cfg(feature = "http")]
+fn default_timeout() -> u64 {
+    30
+}
+
+#[cfg(feature = "http")]
+fn default_concurrency() -> usize {
+    8
+}
+
+#[cfg(feature = "http")]
+fn default_retries() -> u32 {
+    3
+}
+
+#[cfg(feature = "http")]
+fn default_backoff() -> u64 {
+    500
+}
+
+#[cfg(feature = "http")]
+/// Settings of the HTTP client used to talk to a provider.
 #[derive(Deserialize)]
+pub struct HttpConfig {
+    /// Connect timeout in seconds.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Timeout of the whole request in seconds.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Maximum number of concurrent requests.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Maximum number of retries of failed requests.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Initial backoff between retries in milliseconds, doubled after each retry.
+    #[serde(default = "default_backoff")]
+    pub backoff: u64,
+    /// HTTP, HTTPS or SOCKS5 proxy, e.g. `socks5h://127.0.0.1:1080`.
+    pub proxy: Option<String>,
+    /// PEM file of an additional root certificate to trust.
+    pub ca_cert: Option<PathBuf>,
+    pub user_agent: Option<String>,
+}
+
+#[cfg(feature = "http")]
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: default_connect_timeout(),
+            timeout: default_timeout(),
+            concurrency: default_concurrency(),
+            retries: default_retries(),
+            backoff: default_backoff(),
+            proxy: None,
+            ca_cert: None,
+            user_agent: None,
+        }
+    }
+}
+
+#[cfg(feature = "google")]
+#[derive(Deserialize, Default)]
+pub struct GoogleConfig {
+    /// Override the base URL of the API.
+    pub base_url: Option<String>,
+    #[serde(flatten)]
+    pub http: HttpConfig,
+}
+
+#[cfg(feature = "baidu")]
+#[derive(Deserialize, Default)]
 pub struct BaiduConfig {
+    /// Can be overridden by `AYT_BAIDU_APPID`.
+    #[serde(default)]
     pub appid: String,
+    /// Can be overridden by `AYT_BAIDU_SECRET`.
+    #[serde(default)]
     pub secret: String,
+    /// Override the base URL of the API.
+    pub base_url: Option<String>,
+    #[serde(flatten)]
+    pub http: HttpConfig,
 }
 
 #[cfg(feature = "microsoft")]
-#[derive(Deserialize)]
-
+#[derive(Deserialize, Default)]
 pub struct MicrosoftConfig {
+    /// Can be overridden by `AYT_MICROSOFT_API_KEY`.
+    #[serde(default)]
     pub api_key: String,
+    /// Override the base URL of the API.
+    pub base_url: Option<String>,
+    #[serde(flatten)]
+    pub http: HttpConfig,
 }
 
 #[cfg(feature = "deepl")]
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct DeepLConfig {
+    /// Can be overridden by `AYT_DEEPL_AUTH_KEY`.
+    #[serde(default)]
     pub auth_key: String,
+    /// Override the base URL of the API. Chosen from the type of the key by default.
+    pub base_url: Option<String>,
+    #[serde(flatten)]
+    pub http: HttpConfig,
+}
+
+#[cfg(feature = "llm")]
+fn default_glossary_retries() -> u32 {
+    2
+}
+
+/// An OpenAI-compatible chat completion API, including self-hosted llama.cpp and vLLM servers.
+#[cfg(feature = "llm")]
+#[derive(Deserialize, Default)]
+pub struct LlmConfig {
+    /// Name of the model, e.g. `gpt-4o-mini`.
+    #[serde(default)]
+    pub model: String,
+    /// Can be overridden by `AYT_LLM_API_KEY`. Not sent if empty, as local servers need none.
+    #[serde(default)]
+    pub api_key: String,
+    /// Override the base URL of the API, e.g. `http://192.168.1.10:8080/v1`.
+    pub base_url: Option<String>,
+    pub temperature: Option<f64>,
+    /// Instructions appended to the system prompt, such as the style of translations.
+    pub instructions: Option<String>,
+    /// Number of retries when a translation leaves out a term of the glossary.
+    #[serde(default = "default_glossary_retries")]
+    pub glossary_retries: u32,
+    #[serde(flatten)]
+    pub http: HttpConfig,
+}
+
+#[cfg(feature = "libretranslate")]
+#[derive(Deserialize, Default)]
+pub struct LibreTranslateConfig {
+    /// URL of the server, e.g. `http://127.0.0.1:5000`.
+    #[serde(default)]
+    pub url: String,
+    /// Can be overridden by `AYT_LIBRETRANSLATE_API_KEY`. Not sent if empty.
+    #[serde(default)]
+    pub api_key: String,
+    /// Source language. Detected by the server if unset.
+    pub source: Option<String>,
+    #[serde(flatten)]
+    pub http: HttpConfig,
+}
+
+#[cfg(feature = "generic")]
+fn default_generic_method() -> String {
+    "POST".to_owned()
+}
+
+#[cfg(feature = "generic")]
+fn default_generic_source() -> String {
+    "ja".to_owned()
+}
+
+/// Any HTTP translation service. `{text}`, `{source}` and `{target}` are substituted in the URL,
+/// headers and body.
+#[cfg(feature = "generic")]
+#[derive(Deserialize, Default)]
+pub struct GenericConfig {
+    #[serde(default = "default_generic_method")]
+    pub method: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Fields of a form body.
+    pub form: Option<HashMap<String, String>>,
+    /// Template of a JSON body.
+    pub json: Option<serde_json::Value>,
+    /// Path to the translation in the response, e.g. `$.translations[0].text`.
+    #[serde(default)]
+    pub result: String,
+    /// Path to the error message in the response, e.g. `$.error.message`.
+    pub error: Option<String>,
+    #[serde(default = "default_generic_source")]
+    pub source: String,
+    /// Codes of target languages used by the service, e.g. `{ zh = "zh-CN" }`.
+    #[serde(default)]
+    pub languages: HashMap<String, String>,
+    #[serde(flatten)]
+    pub http: HttpConfig,
+}
+
+#[cfg(feature = "process")]
+fn default_process_timeout() -> u64 {
+    60
+}
+
+#[cfg(feature = "process")]
+fn default_process_source() -> String {
+    "ja".to_owned()
+}
+
+/// A local command exchanging newline-delimited JSON with the server. Each request is
+/// `{"id": 1, "text": "...", "source": "ja", "target": "en"}` on its standard input, answered by
+/// `{"id": 1, "text": "..."}` or `{"id": 1, "error": "..."}` on its standard output.
+#[cfg(feature = "process")]
+#[derive(Deserialize, Default)]
+pub struct ProcessConfig {
+    /// Program and its arguments, e.g. `["python3", "translate.py"]`.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Working directory of the command.
+    pub cwd: Option<PathBuf>,
+    /// Seconds to wait for each translation.
+    #[serde(default = "default_process_timeout")]
+    pub timeout: u64,
+    #[serde(default = "default_process_source")]
+    pub source: String,
 }
 
-#[derive(Deserialize, Clone, Copy)]
+#[derive(Deserialize)]
+pub struct QuotaConfig {
+    /// Maximum number of characters to send to the provider per calendar month.
+    pub monthly: Option<u64>,
+    /// Translator to use once the monthly budget is reached. Requests are refused if unset.
+    pub fallback: Option<Translator>,
+    /// Periodically raise usage to the counter reported by the provider, e.g. characters billed
+    /// by DeepL in its billing period.
+    #[serde(default)]
+    pub sync: bool,
+}
+
+/// A client of the DeepL-compatible API.
+#[derive(Deserialize)]
+pub struct ClientConfig {
+    /// Name the usage of the client is recorded under.
+    pub name: String,
+    /// Maximum number of characters the client can translate per calendar month.
+    pub monthly: Option<u64>,
+}
+
+fn default_chat_concurrency() -> usize {
+    2
+}
+
+fn default_chat_queue() -> usize {
+    20
+}
+
+fn default_chat_cache() -> usize {
+    500
+}
+
+/// Limits of each live chat connection.
+#[derive(Deserialize)]
+pub struct ChatConfig {
+    /// Messages translated at the same time.
+    #[serde(default = "default_chat_concurrency")]
+    pub concurrency: usize,
+    /// Messages waiting for translation. The oldest ones are dropped beyond this.
+    #[serde(default = "default_chat_queue")]
+    pub queue: usize,
+    /// Recent translations remembered to answer repeated messages.
+    #[serde(default = "default_chat_cache")]
+    pub cache: usize,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: default_chat_concurrency(),
+            queue: default_chat_queue(),
+            cache: default_chat_cache(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NormalizeConfig {
+    /// Token to collapse `www` laughter into. Laughter is left untouched if unset.
+    pub laughter: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum Translator {
+    #[default]
     Nop,
     #[cfg(feature = "google")]
     Google,
@@ -34,38 +307,221 @@ pub enum Translator {
     Microsoft,
     #[cfg(feature = "deepl")]
     DeepL,
-}
-
-impl Default for Translator {
-    fn default() -> Self {
-        Translator::Nop
-    }
+    #[cfg(feature = "llm")]
+    Llm,
+    #[cfg(feature = "libretranslate")]
+    LibreTranslate,
+    #[cfg(feature = "generic")]
+    Generic,
+    #[cfg(feature = "process")]
+    Process,
 }
 
 fn default_database_path() -> PathBuf {
     PathBuf::from("dictionary.db")
 }
 
+fn default_entities_path() -> PathBuf {
+    PathBuf::from("entities.db")
+}
+
+fn default_memory_path() -> PathBuf {
+    PathBuf::from("memory.db")
+}
+
+fn default_feedback_path() -> PathBuf {
+    PathBuf::from("feedback.db")
+}
+
+fn default_memory_threshold() -> f64 {
+    0.8
+}
+
+fn default_usage_path() -> PathBuf {
+    PathBuf::from("usage.db")
+}
+
+fn default_term_stats_path() -> PathBuf {
+    PathBuf::from("term_stats.db")
+}
+
+fn default_contexts_path() -> PathBuf {
+    PathBuf::from("../web/src/contexts.json")
+}
+
 fn default_listen_addr() -> SocketAddr {
     "127.0.0.1:3001".parse().unwrap()
 }
 
 #[derive(Deserialize)]
 pub struct Config {
+    #[cfg(feature = "google")]
+    #[serde(default)]
+    pub google: GoogleConfig,
     #[cfg(feature = "baidu")]
     pub baidu: Option<BaiduConfig>,
     #[cfg(feature = "microsoft")]
     pub microsoft: Option<MicrosoftConfig>,
     #[cfg(feature = "deepl")]
     pub deepl: Option<DeepLConfig>,
+    #[cfg(feature = "llm")]
+    pub llm: Option<LlmConfig>,
+    #[cfg(feature = "libretranslate")]
+    pub libretranslate: Option<LibreTranslateConfig>,
+    #[cfg(feature = "generic")]
+    pub generic: Option<GenericConfig>,
+    #[cfg(feature = "process")]
+    pub process: Option<ProcessConfig>,
 
     #[serde(default)]
     pub zh: Translator,
     #[serde(default)]
     pub en: Translator,
 
+    /// Normalize input text before matching terms. Disabled if absent.
+    pub normalize: Option<NormalizeConfig>,
+
     #[serde(default = "default_database_path")]
     pub database: PathBuf,
+    #[serde(default = "default_entities_path")]
+    pub entities: PathBuf,
+    /// Translation memory of approved segment pairs.
+    #[serde(default = "default_memory_path")]
+    pub memory: PathBuf,
+    /// Corrections of machine translations submitted by editors.
+    #[serde(default = "default_feedback_path")]
+    pub feedback: PathBuf,
+    /// Characters sent to each provider.
+    #[serde(default = "default_usage_path")]
+    pub usage: PathBuf,
+    /// Matches of each term.
+    #[serde(default = "default_term_stats_path")]
+    pub term_stats: PathBuf,
+    /// Monthly budgets keyed by translator, e.g. `[quota.deepl]`.
+    #[serde(default)]
+    pub quota: HashMap<String, QuotaConfig>,
+    /// Record translated source texts to mine name candidates from. Disabled if absent.
+    pub traffic: Option<PathBuf>,
+    /// Minimum similarity for a translation memory entry to be suggested.
+    #[serde(default = "default_memory_threshold")]
+    pub memory_threshold: f64,
+    /// Context registry used to infer contexts from the text being translated.
+    #[serde(default = "default_contexts_path")]
+    pub contexts: PathBuf,
+    /// Map from author handles to the context their texts belong to.
+    #[serde(default)]
+    pub authors: HashMap<String, String>,
+    #[serde(default)]
+    pub chat: ChatConfig,
+    /// Clients of the DeepL-compatible API keyed by their auth key, e.g. `[clients."secret"]`.
+    /// All requests to the API are refused if empty.
+    #[serde(default)]
+    pub clients: HashMap<String, ClientConfig>,
     #[serde(default = "default_listen_addr")]
     pub listen: SocketAddr,
 }
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut config: Self = toml::from_str(
+            &std::fs::read_to_string(path)
+                .with_context(|| format!("Cannot load {}", path.display()))?,
+        )
+        .with_context(|| format!("Cannot parse {}", path.display()))?;
+        config.apply_env();
+        Ok(config)
+    }
+
+    /// Override secrets with environment variables, so they need not be stored in the file.
+    fn apply_env(&mut self) {
+        #[cfg(any(
+            feature = "baidu",
+            feature = "microsoft",
+            feature = "deepl",
+            feature = "llm",
+            feature = "libretranslate"
+        ))]
+        let var = |name: &str| std::env::var(name).ok().filter(|x| !x.is_empty());
+
+        #[cfg(feature = "baidu")]
+        if let Some(appid) = var("AYT_BAIDU_APPID") {
+            self.baidu.get_or_insert_with(Default::default).appid = appid;
+        }
+        #[cfg(feature = "baidu")]
+        if let Some(secret) = var("AYT_BAIDU_SECRET") {
+            self.baidu.get_or_insert_with(Default::default).secret = secret;
+        }
+        #[cfg(feature = "microsoft")]
+        if let Some(api_key) = var("AYT_MICROSOFT_API_KEY") {
+            self.microsoft.get_or_insert_with(Default::default).api_key = api_key;
+        }
+        #[cfg(feature = "deepl")]
+        if let Some(auth_key) = var("AYT_DEEPL_AUTH_KEY") {
+            self.deepl.get_or_insert_with(Default::default).auth_key = auth_key;
+        }
+        // The key is optional, so it does not enable the section by itself.
+        #[cfg(feature = "llm")]
+        if let (Some(api_key), Some(config)) = (var("AYT_LLM_API_KEY"), &mut self.llm) {
+            config.api_key = api_key;
+        }
+        #[cfg(feature = "libretranslate")]
+        if let (Some(api_key), Some(config)) =
+            (var("AYT_LIBRETRANSLATE_API_KEY"), &mut self.libretranslate)
+        {
+            config.api_key = api_key;
+        }
+    }
+
+    /// Check constraints that cannot be expressed in the types of the fields.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(0.0..=1.0).contains(&self.memory_threshold) {
+            anyhow::bail!("memory_threshold must be between 0 and 1");
+        }
+        if self.chat.queue == 0 {
+            anyhow::bail!("chat.queue must be at least 1");
+        }
+        #[cfg(feature = "baidu")]
+        if let Some(config) = &self.baidu {
+            if config.appid.is_empty() || config.secret.is_empty() {
+                anyhow::bail!("Baidu appid and secret must be set");
+            }
+        }
+        #[cfg(feature = "microsoft")]
+        if let Some(config) = &self.microsoft {
+            if config.api_key.is_empty() {
+                anyhow::bail!("Microsoft api_key must be set");
+            }
+        }
+        #[cfg(feature = "deepl")]
+        if let Some(config) = &self.deepl {
+            if config.auth_key.is_empty() {
+                anyhow::bail!("DeepL auth_key must be set");
+            }
+        }
+        #[cfg(feature = "llm")]
+        if let Some(config) = &self.llm {
+            if config.model.is_empty() {
+                anyhow::bail!("LLM model must be set");
+            }
+        }
+        #[cfg(feature = "libretranslate")]
+        if let Some(config) = &self.libretranslate {
+            if config.url.is_empty() {
+                anyhow::bail!("LibreTranslate url must be set");
+            }
+        }
+        #[cfg(feature = "generic")]
+        if let Some(config) = &self.generic {
+            if config.url.is_empty() || config.result.is_empty() {
+                anyhow::bail!("Generic translator url and result must be set");
+            }
+        }
+        #[cfg(feature = "process")]
+        if let Some(config) = &self.process {
+            if config.command.is_empty() {
+                anyhow::bail!("Process command must be set");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A known context, in the same shape as `web/src/contexts.json`.
+#[derive(Deserialize)]
+pub struct Context {
+    /// Space-separated keywords that identify this context.
+    pub search: String,
+    /// Name of the context, as referenced by `RegexTerm::context`.
+    pub text: String,
+    /// Background given to translators that follow a glossary, such as who the member is.
+    #[serde(default)]
+    pub description: String,
+}
+
+pub struct ContextRegistry {
+    contexts: Vec<Context>,
+    /// Map from lowercased author handles to context names.
+    authors: HashMap<String, String>,
+}
+
+fn normalize_handle(handle: &str) -> String {
+    handle.trim().trim_start_matches('@').to_lowercase()
+}
+
+impl ContextRegistry {
+    pub fn new(contexts: Vec<Context>, authors: &HashMap<String, String>) -> Self {
+        Self {
+            contexts,
+            authors: authors
+                .iter()
+                .map(|(k, v)| (normalize_handle(k), v.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>, authors: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contexts = if path.exists() {
+            serde_json::from_slice(&std::fs::read(path)?)?
+        } else {
+            log::warn!("Context registry {} not found", path.display());
+            Vec::new()
+        };
+        Ok(Self::new(contexts, authors))
+    }
+
+    /// Describe contexts by name, for translators that follow a glossary.
+    pub fn describe(&self, names: &[String]) -> Vec<String> {
+        names
+            .iter()
+            .map(
+                |name| match self.contexts.iter().find(|x| &x.text == name) {
+                    Some(context) if !context.description.is_empty() => {
+                        format!("{}: {}", name, context.description)
+                    }
+                    _ => name.clone(),
+                },
+            )
+            .collect()
+    }
+
+    /// Infer active contexts from the author of the text and the names mentioned in it.
+    pub fn infer(&self, author: Option<&str>, text: &str) -> Vec<String> {
+        let mut active = Vec::new();
+
+        if let Some(context) = author.and_then(|x| self.authors.get(&normalize_handle(x))) {
+            active.push(context.clone());
+        }
+
+        let text = text.to_lowercase();
+        for context in &self.contexts {
+            if active.contains(&context.text) {
+                continue;
+            }
+
+            // Single characters are too ambiguous to identify a context.
+            let mentioned = context
+                .search
+                .split_whitespace()
+                .filter(|x| x.chars().count() >= 2)
+                .any(|x| text.contains(&x.to_lowercase()));
+            if mentioned {
+                active.push(context.text.clone());
+            }
+        }
+
+        active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> ContextRegistry {
+        let contexts = serde_json::from_value(serde_json::json!([
+            { "search": "星街すいせい すいせい 彗 suisei", "text": "Suisei", "description": "VTuber" },
+            { "search": "さくらみこ みこち", "text": "Miko" },
+        ]))
+        .unwrap();
+        let authors = HashMap::from([("@Suisei_hosimati".to_owned(), "Suisei".to_owned())]);
+        ContextRegistry::new(contexts, &authors)
+    }
+
+    #[test]
+    fn infers_contexts_from_author_and_names() {
+        let registry = registry();
+        assert_eq!(
+            registry.infer(Some("suisei_hosimati"), "みこちとコラボ！"),
+            ["Suisei", "Miko"]
+        );
+        assert_eq!(registry.infer(None, "SUISEI is here"), ["Suisei"]);
+        assert!(registry.infer(Some("someone"), "こんにちは").is_empty());
+        // Single characters such as 彗 are too ambiguous.
+        assert!(registry.infer(None, "彗星").is_empty());
+    }
+
+    #[test]
+    fn describes_contexts() {
+        let names = ["Suisei".to_owned(), "Miko".to_owned(), "Other".to_owned()];
+        assert_eq!(
+            registry().describe(&names),
+            ["Suisei: VTuber", "Miko", "Other"]
+        );
+    }
+}
@@ -8,6 +8,9 @@ use std::io::Read;
 use std::ops::Deref;
 use std::path::Path;
 use std::sync::RwLockReadGuard;
+use std::time::Instant;
+
+use crate::metrics;
 
 #[derive(Serialize, Deserialize)]
 pub struct Keyed<K, V> {
@@ -58,12 +61,14 @@ where
             }
             Ok(vec.into_iter().collect())
         })()
-        .map_err(|e| DeSerError::Other(e))
+        .map_err(DeSerError::Other)
     }
 }
 
 pub struct Database<K, V> {
     pub db: PathDatabase<HashMap<K, V>, Json>,
+    /// Name of the file, used to label metrics.
+    name: String,
 }
 
 impl<K, V> Database<K, V>
@@ -72,8 +77,33 @@ where
     V: Serialize + DeserializeOwned + Clone + Send,
 {
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        let db = PathDatabase::load_from_path_or_default(path.as_ref().into())?;
-        Ok(Database { db })
+        let path = path.as_ref();
+        let db = PathDatabase::load_from_path_or_default(path.into())?;
+        let name = path
+            .file_stem()
+            .map_or_else(String::new, |x| x.to_string_lossy().into_owned());
+        let database = Database { db, name };
+        database.observe_size()?;
+        Ok(database)
+    }
+
+    /// Write the database to disk.
+    pub fn save(&self) -> Result<()> {
+        let start = Instant::now();
+        self.db.save()?;
+        metrics::DATABASE_SAVE_DURATION
+            .with_label_values(&[&self.name])
+            .observe(start.elapsed().as_secs_f64());
+        self.observe_size()
+    }
+
+    /// Report the number of entries in the metrics.
+    fn observe_size(&self) -> Result<()> {
+        let len = self.db.borrow_data()?.len();
+        metrics::DATABASE_ENTRIES
+            .with_label_values(&[&self.name])
+            .set(len as i64);
+        Ok(())
     }
 
     pub fn iter(&self) -> Result<impl Iterator<Item = Keyed<&K, &V>>> {
@@ -104,10 +134,10 @@ where
         })
     }
 
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Result<Option<impl Deref<Target = Keyed<&K, &V>>>>
+    pub fn get<Q>(&self, k: &Q) -> Result<Option<impl Deref<Target = Keyed<&K, &V>>>>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: ?Sized + Hash + Eq,
     {
         struct Wrapper<'a, K, V, D> {
             data: D,
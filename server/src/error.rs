@@ -0,0 +1,208 @@
+use serde::Serialize;
+use warp::http::StatusCode;
+
+use crate::usage::QuotaExceeded;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Resource {
+    Term,
+    Entity,
+    MemoryEntry,
+}
+
+/// Errors reported to API clients with a stable code.
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(Resource, String),
+    UnsupportedLanguage(String),
+    UnsupportedSourceLanguage(String),
+    /// The term cannot be parsed, e.g. because its regex is invalid.
+    InvalidTerm {
+        message: String,
+        /// Position in the regex where parsing failed.
+        position: Option<usize>,
+    },
+    InvalidBody(String),
+    InvalidQuery(String),
+    /// An optional feature that is not enabled in the config.
+    Disabled(&'static str),
+    UpstreamFailed {
+        provider: &'static str,
+        source: anyhow::Error,
+    },
+    QuotaExceeded(QuotaExceeded),
+    /// The request changes a term without `If-Match`.
+    PreconditionRequired,
+    /// The term has changed since the version given in `If-Match`.
+    Modified {
+        id: String,
+        /// Current value of the term.
+        current: serde_json::Value,
+    },
+}
+
+/// Error of a provider while translating, as opposed to an error of the server itself.
+#[derive(Debug)]
+pub struct ProviderError {
+    pub provider: &'static str,
+    pub source: anyhow::Error,
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} failed: {}", self.provider, self.source)
+    }
+}
+
+impl std::error::Error for ProviderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct ErrorMessage {
+    pub error: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<serde_json::Value>,
+}
+
+impl ErrorMessage {
+    pub fn new(error: &str, message: String) -> Self {
+        Self {
+            error: error.into(),
+            message,
+            provider: None,
+            position: None,
+            current: None,
+        }
+    }
+
+    /// Describe an error to clients.
+    ///
+    /// Errors other than [`ApiError`] are only logged, as their details are internal to the
+    /// server.
+    pub fn describe(err: &anyhow::Error) -> (StatusCode, Self) {
+        match err.downcast_ref::<ApiError>() {
+            Some(err) => (err.status(), err.to_message()),
+            None => {
+                log::error!("Internal server error: {:?}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Self::new("INTERNAL_SERVER_ERROR", "Internal server error".into()),
+                )
+            }
+        }
+    }
+}
+
+impl ApiError {
+    /// Report errors of providers from translation to clients, including exhausted quotas. Other
+    /// errors are left as they are.
+    pub fn upstream(err: anyhow::Error) -> anyhow::Error {
+        let err = match err.downcast::<QuotaExceeded>() {
+            Ok(err) => return ApiError::QuotaExceeded(err).into(),
+            Err(err) => err,
+        };
+        match err.downcast::<ProviderError>() {
+            Ok(ProviderError { provider, source }) => {
+                ApiError::UpstreamFailed { provider, source }.into()
+            }
+            Err(err) => err,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(Resource::Term, _) => "TERM_NOT_FOUND",
+            ApiError::NotFound(Resource::Entity, _) => "ENTITY_NOT_FOUND",
+            ApiError::NotFound(Resource::MemoryEntry, _) => "MEMORY_ENTRY_NOT_FOUND",
+            ApiError::UnsupportedLanguage(_) | ApiError::UnsupportedSourceLanguage(_) => {
+                "UNSUPPORTED_LANGUAGE"
+            }
+            ApiError::InvalidTerm { .. } => "INVALID_TERM",
+            ApiError::InvalidBody(_) => "INVALID_BODY",
+            ApiError::InvalidQuery(_) => "INVALID_QUERY",
+            ApiError::Disabled(_) => "FEATURE_DISABLED",
+            ApiError::UpstreamFailed { .. } => "UPSTREAM_FAILED",
+            ApiError::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+            ApiError::PreconditionRequired => "PRECONDITION_REQUIRED",
+            ApiError::Modified { .. } => "PRECONDITION_FAILED",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(..) | ApiError::Disabled(_) => StatusCode::NOT_FOUND,
+            ApiError::UnsupportedLanguage(_) | ApiError::UnsupportedSourceLanguage(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            ApiError::InvalidTerm { .. } | ApiError::InvalidBody(_) | ApiError::InvalidQuery(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::UpstreamFailed { .. } => StatusCode::BAD_GATEWAY,
+            ApiError::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::Modified { .. } => StatusCode::PRECONDITION_FAILED,
+        }
+    }
+
+    pub fn to_message(&self) -> ErrorMessage {
+        ErrorMessage {
+            error: self.code().into(),
+            message: self.to_string(),
+            provider: match self {
+                ApiError::UpstreamFailed { provider, .. } => Some(provider),
+                ApiError::QuotaExceeded(err) => Some(err.provider),
+                _ => None,
+            },
+            position: match self {
+                ApiError::InvalidTerm { position, .. } => *position,
+                _ => None,
+            },
+            current: match self {
+                ApiError::Modified { current, .. } => Some(current.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::NotFound(Resource::Term, id) => write!(f, "Term ID {} does not exist", id),
+            ApiError::NotFound(Resource::Entity, id) => {
+                write!(f, "Entity ID {} does not exist", id)
+            }
+            ApiError::NotFound(Resource::MemoryEntry, id) => {
+                write!(f, "Memory entry ID {} does not exist", id)
+            }
+            ApiError::UnsupportedLanguage(lang) => {
+                write!(f, "Target language {} is not supported", lang)
+            }
+            ApiError::UnsupportedSourceLanguage(lang) => {
+                write!(f, "Source language {} is not supported", lang)
+            }
+            ApiError::InvalidTerm { message, .. } => write!(f, "Invalid term: {}", message),
+            ApiError::InvalidBody(message) => write!(f, "Invalid body: {}", message),
+            ApiError::InvalidQuery(message) => write!(f, "Invalid query: {}", message),
+            ApiError::Disabled(feature) => write!(f, "{} is not enabled", feature),
+            ApiError::UpstreamFailed { provider, source } => {
+                write!(f, "{} failed: {}", provider, source)
+            }
+            ApiError::QuotaExceeded(err) => err.fmt(f),
+            ApiError::PreconditionRequired => {
+                write!(f, "If-Match is required to change a term")
+            }
+            ApiError::Modified { id, .. } => write!(f, "Term ID {} has been modified", id),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
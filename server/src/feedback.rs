@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+use similar::{Algorithm, DiffTag};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::db::Database;
+use crate::schema::{FilterList, RegexTerm, TermType};
+
+/// A human correction of a machine translation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Feedback {
+    pub source: String,
+    /// Output of the machine translation pipeline.
+    pub output: String,
+    pub corrected: String,
+    /// Name of the machine translator that produced the output.
+    pub provider: String,
+    #[serde(rename = "targetLang")]
+    pub target_lang: String,
+    /// Time the feedback was received, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub time: u64,
+}
+
+/// A term proposed from corrections, for an editor to review.
+#[derive(Serialize)]
+pub struct TermSuggestion {
+    pub term: RegexTerm,
+    /// Number of corrections that made the same change.
+    pub count: usize,
+    /// IDs of the feedback this suggestion is derived from.
+    pub feedback: Vec<String>,
+}
+
+/// Split text into words of ASCII alphanumerics and single other characters.
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut iter = text.char_indices().peekable();
+    while let Some((start, c)) = iter.next() {
+        let mut end = start + c.len_utf8();
+        if c.is_ascii_alphanumeric() {
+            while let Some(&(i, c)) = iter.peek() {
+                if !c.is_ascii_alphanumeric() {
+                    break;
+                }
+                end = i + c.len_utf8();
+                iter.next();
+            }
+        }
+        tokens.push(&text[start..end]);
+    }
+    tokens
+}
+
+fn is_insignificant(text: &str) -> bool {
+    text.chars()
+        .all(|c| c.is_whitespace() || c.is_ascii_punctuation() || !c.is_alphanumeric())
+}
+
+/// Find the replacements a correction made to the machine translation.
+fn replacements(output: &str, corrected: &str) -> Vec<(String, String)> {
+    let old = tokenize(output);
+    let new = tokenize(corrected);
+    let ops = similar::capture_diff_slices(Algorithm::Myers, &old, &new);
+
+    // Merge changes separated by a single unchanged token, e.g. both words of a misspelled name.
+    let mut hunks: Vec<(std::ops::Range<usize>, std::ops::Range<usize>)> = Vec::new();
+    let mut gap = None;
+    for op in ops {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            gap = Some(old_range);
+            continue;
+        }
+        let mergeable = match (&gap, hunks.last()) {
+            (Some(gap), Some(_)) => gap.len() == 1 && !old[gap.start].trim().is_empty(),
+            (None, Some(_)) => true,
+            _ => false,
+        };
+        if mergeable {
+            let last = hunks.last_mut().unwrap();
+            last.0.end = old_range.end;
+            last.1.end = new_range.end;
+        } else {
+            hunks.push((old_range, new_range));
+        }
+        gap = None;
+    }
+
+    hunks
+        .into_iter()
+        .map(|(old_range, new_range)| {
+            (
+                old[old_range].concat().trim().to_owned(),
+                new[new_range].concat().trim().to_owned(),
+            )
+        })
+        .filter(|(wrong, right)| !is_insignificant(wrong) && !is_insignificant(right))
+        .collect()
+}
+
+/// Propose postprocessing terms that apply recurring corrections.
+pub fn suggest_terms(
+    feedback: &Database<String, Feedback>,
+    terms: &Database<String, RegexTerm>,
+    min_count: usize,
+) -> anyhow::Result<Vec<TermSuggestion>> {
+    struct Group {
+        providers: BTreeSet<String>,
+        feedback: Vec<String>,
+    }
+
+    let mut groups: BTreeMap<(String, String, String), Group> = BTreeMap::new();
+    for item in feedback.iter()? {
+        let value = item.value;
+        for (wrong, right) in replacements(&value.output, &value.corrected) {
+            let group = groups
+                .entry((value.target_lang.clone(), wrong, right))
+                .or_insert_with(|| Group {
+                    providers: BTreeSet::new(),
+                    feedback: Vec::new(),
+                });
+            group.providers.insert(value.provider.clone());
+            if !group.feedback.contains(item.key) {
+                group.feedback.push(item.key.clone());
+            }
+        }
+    }
+
+    let existing: Vec<_> = terms
+        .iter()?
+        .map(|x| x.value)
+        .filter(|x| x.ty == TermType::Postprocess)
+        .cloned()
+        .collect();
+
+    let mut suggestions = Vec::new();
+    for ((target_lang, wrong, right), group) in groups {
+        if group.feedback.len() < min_count {
+            continue;
+        }
+
+        // Skip corrections that an existing term already performs.
+        let covered = existing.iter().any(|x| {
+            x.output == right
+                && x.target_lang.as_deref().is_none_or(|x| x == target_lang)
+                && x.input.is_match(&wrong).unwrap_or(false)
+        });
+        if covered {
+            continue;
+        }
+
+        suggestions.push(TermSuggestion {
+            term: RegexTerm {
+                input: fancy_regex::Regex::new(&regex::escape(&wrong))?,
+                output: right,
+                target_lang: Some(target_lang),
+                translator: Some(FilterList::new(
+                    false,
+                    group.providers.into_iter().collect(),
+                )),
+                priority: 0,
+                context: None,
+                ty: TermType::Postprocess,
+                comment: format!("Suggested from {} correction(s)", group.feedback.len()),
+                version: 0,
+                updated_at: 0,
+                id: None,
+            },
+            count: group.feedback.len(),
+            feedback: group.feedback,
+        });
+    }
+
+    suggestions.sort_by_key(|x| std::cmp::Reverse(x.count));
+    Ok(suggestions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes_words_and_characters() {
+        assert_eq!(
+            tokenize("Hi Suisei2, 彗星!"),
+            ["Hi", " ", "Suisei2", ",", " ", "彗", "星", "!"]
+        );
+    }
+
+    #[test]
+    fn finds_replacements() {
+        assert_eq!(
+            replacements("Thank you, Comet-chan!", "Thank you, Suisei-chan!"),
+            [("Comet".to_owned(), "Suisei".to_owned())]
+        );
+        // Changes separated by a single token are merged, as for both parts of a name.
+        assert_eq!(
+            replacements("I love Star-Street", "I love Hoshi-Machi"),
+            [("Star-Street".to_owned(), "Hoshi-Machi".to_owned())]
+        );
+        assert_eq!(replacements("Star and Comet", "Sora and Suisei").len(), 2);
+        // Punctuation and whitespace changes are not worth a term.
+        assert!(replacements("Good morning.", "Good morning!").is_empty());
+        assert!(replacements("same", "same").is_empty());
+    }
+}
@@ -1,100 +1,40 @@
-use anyhow::Context;
-use once_cell::sync::Lazy;
+use clap::Parser;
+use once_cell::sync::OnceCell;
+use std::path::PathBuf;
 use std::sync::Arc;
-use translator::Translator;
 use warp::Filter;
 
 mod api;
+mod chat;
+mod cli;
+mod compat;
 mod config;
+mod context;
 mod db;
+mod error;
+mod feedback;
+mod memory;
+mod metrics;
+mod pipeline;
+mod query;
 mod regex;
+mod runtime;
 mod schema;
+mod stats;
+mod subtitle;
+mod traffic;
 mod translator;
+mod usage;
 
-use schema::RegexTerm;
+use schema::{Entity, RegexTerm};
 
-static CONFIG: Lazy<config::Config> = Lazy::new(|| {
-    fn load_config() -> anyhow::Result<config::Config> {
-        Ok(toml::from_str(
-            &std::fs::read_to_string("config.toml").with_context(|| "Cannot load config.toml")?,
-        )
-        .with_context(|| "Cannot parse config.toml")?)
-    }
+static RUNTIME: OnceCell<Arc<runtime::Handle>> = OnceCell::new();
 
-    match load_config() {
-        Ok(v) => v,
-        Err(err) => {
-            eprintln!("{:?}", err);
-            std::process::exit(1);
-        }
-    }
-});
-
-fn load_translator(
-    target_lang: &str,
-    translator: config::Translator,
-) -> anyhow::Result<Box<dyn Translator>> {
-    Ok(match translator {
-        config::Translator::Nop => Box::new(translator::NopTranslator),
-        #[cfg(feature = "google")]
-        config::Translator::Google => {
-            Box::new(translator::GoogleTranslator::new(target_lang.to_owned()))
-        }
-        #[cfg(feature = "baidu")]
-        config::Translator::Baidu => {
-            let config = CONFIG
-                .baidu
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Baidu config not found"))?;
-            Box::new(translator::BaiduTranslator::new(
-                config.appid.clone(),
-                config.secret.clone(),
-                target_lang.to_owned(),
-            ))
-        }
-        #[cfg(feature = "microsoft")]
-        config::Translator::Microsoft => {
-            let config = CONFIG
-                .microsoft
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Microsoft config not found"))?;
-            Box::new(translator::MicrosoftTranslator::new(
-                config.api_key.clone(),
-                target_lang.to_owned(),
-            ))
-        }
-        #[cfg(feature = "deepl")]
-        config::Translator::DeepL => {
-            let config = CONFIG
-                .deepl
-                .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("DeepL config not found"))?;
-            Box::new(translator::DeepLTranslator::new(
-                config.auth_key.clone(),
-                target_lang.to_owned(),
-            ))
-        }
-    })
+/// Get the current runtime. Requests should hold on to it until they finish.
+pub fn runtime() -> Arc<runtime::Runtime> {
+    RUNTIME.get().expect("runtime not initialized").load()
 }
 
-static TRANSLATOR_EN: Lazy<Box<dyn Translator>> =
-    Lazy::new(|| match load_translator("en", CONFIG.en) {
-        Ok(v) => v,
-        Err(err) => {
-            eprintln!("{:?}", err);
-            std::process::exit(1);
-        }
-    });
-
-static TRANSLATOR_ZH: Lazy<Box<dyn Translator>> =
-    Lazy::new(|| match load_translator("zh", CONFIG.zh) {
-        Ok(v) => v,
-        Err(err) => {
-            eprintln!("{:?}", err);
-            std::process::exit(1);
-        }
-    });
-
 #[derive(Debug)]
 pub struct WarpError(pub anyhow::Error);
 
@@ -109,41 +49,40 @@ where
     }
 }
 
-#[derive(serde::Serialize)]
-pub struct ErrorMessage {
-    pub error: String,
-}
-
 pub async fn handle_rejection(
     err: warp::Rejection,
 ) -> Result<impl warp::Reply, std::convert::Infallible> {
+    use error::ErrorMessage;
     use warp::http::StatusCode;
 
+    let simple = ErrorMessage::new;
+
     let code;
     let message;
 
     if err.is_not_found() {
         code = StatusCode::NOT_FOUND;
-        message = "NOT_FOUND";
-    } else if let Some(WarpError(_err)) = err.find::<WarpError>() {
-        code = StatusCode::INTERNAL_SERVER_ERROR;
-        message = "INTERNAL_SERVER_ERROR";
-    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
+        message = simple("NOT_FOUND", "Not found".into());
+    } else if let Some(WarpError(err)) = err.find::<WarpError>() {
+        (code, message) = ErrorMessage::describe(err);
+    } else if let Some(err) = err.find::<warp::reject::InvalidQuery>() {
+        code = StatusCode::UNPROCESSABLE_ENTITY;
+        message = simple("INVALID_QUERY", err.to_string());
+    } else if let Some(err) = err.find::<warp::filters::body::BodyDeserializeError>() {
         code = StatusCode::UNPROCESSABLE_ENTITY;
-        message = "INVALID_QUERY";
+        message = simple("INVALID_BODY", err.to_string());
     } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
         code = StatusCode::METHOD_NOT_ALLOWED;
-        message = "METHOD_NOT_ALLOWED";
+        message = simple("METHOD_NOT_ALLOWED", "Method not allowed".into());
     } else {
         code = StatusCode::INTERNAL_SERVER_ERROR;
-        message = "UNHANDLED_REJECTION";
+        // Details are only logged below, as they are internal to the server.
+        message = simple("UNHANDLED_REJECTION", "Unhandled rejection".into());
     }
 
     log::info!("{:?}", err);
 
-    let json = warp::reply::json(&ErrorMessage {
-        error: message.into(),
-    });
+    let json = warp::reply::json(&message);
 
     Ok(warp::reply::with_status(json, code))
 }
@@ -152,25 +91,157 @@ pub async fn handle_rejection(
 async fn main() -> anyhow::Result<()> {
     pretty_env_logger::init();
 
-    Lazy::force(&TRANSLATOR_EN);
-    Lazy::force(&TRANSLATOR_ZH);
+    let cli = cli::Cli::parse();
+    match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => serve(cli.config).await,
+        cli::Command::Terms {
+            database,
+            term_stats,
+            command,
+        } => {
+            let (database, term_stats) = match (database, term_stats) {
+                (Some(database), Some(term_stats)) => (database, term_stats),
+                (database, term_stats) => {
+                    let config = config::Config::load(&cli.config)?;
+                    (
+                        database.unwrap_or(config.database),
+                        term_stats.unwrap_or(config.term_stats),
+                    )
+                }
+            };
+            cli::terms::run(&database, &term_stats, command)
+        }
+        cli::Command::Translate(args) => {
+            cli::translate::run(config::Config::load(&cli.config)?, args).await
+        }
+    }
+}
+
+/// Serve the API, reloading the configuration file on SIGHUP or when modified.
+async fn serve(config_path: PathBuf) -> anyhow::Result<()> {
+    let config = match config::Config::load(&config_path) {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("{:?}", err);
+            std::process::exit(1);
+        }
+    };
+    let listen = config.listen;
+
+    let db = Arc::new(db::Database::<String, RegexTerm>::open(&config.database).unwrap());
+    let entities = Arc::new(db::Database::<String, Entity>::open(&config.entities).unwrap());
+    let memory =
+        Arc::new(db::Database::<String, memory::MemoryEntry>::open(&config.memory).unwrap());
+    let feedback =
+        Arc::new(db::Database::<String, feedback::Feedback>::open(&config.feedback).unwrap());
+    let traffic = config
+        .traffic
+        .as_ref()
+        .map(|path| Arc::new(traffic::Traffic::open(path).unwrap()));
+    let usage = Arc::new(usage::Usage::open(&config.usage).unwrap());
+    let stats = Arc::new(stats::Stats::open(&config.term_stats).unwrap());
+
+    let initial = match runtime::Runtime::new(config, &usage) {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("{:?}", err);
+            std::process::exit(1);
+        }
+    };
+    let handle = Arc::new(runtime::Handle::new(config_path, usage.clone(), initial));
+    RUNTIME.set(handle.clone()).ok().unwrap();
+    tokio::spawn(handle.watch());
+
+    // Keep usage in sync with providers that report it.
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(15 * 60));
+        loop {
+            interval.tick().await;
+            let runtime = runtime();
+            for translator in [&runtime.translator_en, &runtime.translator_zh] {
+                if let Err(err) = translator.sync_usage().await {
+                    log::warn!("Cannot sync usage of {}: {:?}", translator.name(), err);
+                }
+            }
+        }
+    });
 
-    let db = Arc::new(db::Database::<String, RegexTerm>::open(&CONFIG.database).unwrap());
+    let pipeline = Arc::new(pipeline::Pipeline {
+        terms: db.clone(),
+        entities: entities.clone(),
+        memory: memory.clone(),
+        stats: Some(stats.clone()),
+    });
 
     // Dispatch api with the rest served by static files.
     let routes = warp::path("api")
         .and(
-            api::api_get_terms(db.clone())
+            api::api_get_term_candidates(db.clone(), entities.clone(), traffic.clone())
+                .or(api::api_get_term_stats(db.clone(), stats.clone()))
+                .or(api::api_get_terms(db.clone(), stats.clone()))
                 .or(api::api_get_term(db.clone()))
                 .or(api::api_post_term(db.clone()))
                 .or(api::api_put_term(db.clone()))
-                .or(api::api_delete_term(db.clone()))
-                .or(api::api_post_translate(db.clone()))
+                .or(api::api_patch_term(db.clone()))
+                .or(api::api_delete_term(db.clone(), stats.clone()))
+                .or(api::api_get_entities(entities.clone()))
+                .or(api::api_get_entity(entities.clone()))
+                .or(api::api_post_entity(entities.clone()))
+                .or(api::api_put_entity(entities.clone()))
+                .or(api::api_delete_entity(entities.clone()))
+                .or(api::api_get_memory(memory.clone()))
+                .or(api::api_post_memory(memory.clone()))
+                .or(api::api_delete_memory(memory.clone()))
+                .or(api::api_post_memory_tmx(memory.clone()))
+                .or(api::api_get_usage(usage.clone()))
+                .or(api::api_get_feedback(feedback.clone()))
+                .or(api::api_post_feedback(feedback.clone()))
+                .or(api::api_get_feedback_terms(feedback.clone(), db.clone()))
+                .or(api::api_post_translate(pipeline.clone(), traffic.clone()))
                 .map(|reply| warp::reply::with_header(reply, "content-type", "application/json"))
+                .or(api::api_get_memory_tmx(memory.clone()))
+                .or(api::api_post_translate_subtitles(pipeline.clone()))
+                .or(api::api_chat(pipeline.clone()))
                 .recover(handle_rejection),
         )
-        .or(warp::fs::dir("../web/dist"));
-
-    warp::serve(routes).run(CONFIG.listen).await;
+        .or(compat::libretranslate::routes(pipeline.clone()))
+        .or(compat::deepl::routes(pipeline, usage.clone()))
+        .or(api::api_get_metrics())
+        .or(warp::fs::dir("../web/dist"))
+        .with(api::log_metrics());
+
+    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(listen, shutdown_signal());
+    server.await;
+
+    // Counters and records are saved periodically, so save what has changed since.
+    usage.save()?;
+    stats.save()?;
+    if let Some(traffic) = traffic {
+        traffic.save()?;
+    }
     Ok(())
 }
+
+/// Wait for SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                log::warn!("Cannot listen for SIGTERM: {:?}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate => (),
+    }
+    log::info!("Shutting down");
+}
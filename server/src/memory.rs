@@ -0,0 +1,366 @@
+use anyhow::Context;
+use quick_xml::events::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::db::Database;
+
+/// An approved translation of a source segment.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MemoryEntry {
+    pub source: String,
+    pub target: String,
+    /// Language code of the target segment. The source is always Japanese.
+    #[serde(rename = "targetLang")]
+    pub target_lang: String,
+}
+
+/// A previously approved translation similar to the text being translated.
+#[derive(Serialize, Debug, Clone)]
+pub struct Suggestion {
+    pub source: String,
+    pub target: String,
+    pub score: f64,
+}
+
+const SOURCE_LANG: &str = "ja";
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// Similarity between two segments, from 0 (unrelated) to 1 (identical).
+fn similarity(a: &[char], b: &[char]) -> f64 {
+    let max = a.len().max(b.len());
+    if max == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(a, b) as f64 / max as f64
+}
+
+/// Find an approved translation of exactly the given segment.
+pub fn find_exact(
+    db: &Database<String, MemoryEntry>,
+    target_lang: &str,
+    source: &str,
+) -> anyhow::Result<Option<String>> {
+    let source = source.trim();
+    Ok(db
+        .iter()?
+        .find(|x| x.value.target_lang == target_lang && x.value.source.trim() == source)
+        .map(|x| x.value.target.clone()))
+}
+
+/// Find approved translations of segments similar to the given one, best match first.
+pub fn find_fuzzy(
+    db: &Database<String, MemoryEntry>,
+    target_lang: &str,
+    source: &str,
+    threshold: f64,
+    limit: usize,
+) -> anyhow::Result<Vec<Suggestion>> {
+    let source: Vec<char> = source.trim().chars().collect();
+    if source.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut suggestions = Vec::new();
+    for entry in db.iter()? {
+        let entry = entry.value;
+        if entry.target_lang != target_lang {
+            continue;
+        }
+
+        // Skip segments whose length difference alone already rules them out.
+        let candidate: Vec<char> = entry.source.trim().chars().collect();
+        let max = source.len().max(candidate.len()) as f64;
+        if source.len().abs_diff(candidate.len()) as f64 / max > 1.0 - threshold {
+            continue;
+        }
+
+        let score = similarity(&source, &candidate);
+        if score >= threshold && score < 1.0 {
+            suggestions.push(Suggestion {
+                source: entry.source.clone(),
+                target: entry.target.clone(),
+                score,
+            });
+        }
+    }
+
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions.truncate(limit);
+    Ok(suggestions)
+}
+
+/// Add entries to the memory, replacing the translation of segments that already exist.
+pub fn insert(
+    db: &Database<String, MemoryEntry>,
+    entries: Vec<MemoryEntry>,
+) -> anyhow::Result<Vec<String>> {
+    let keys = db.db.write(|map| {
+        let mut existing: HashMap<(String, String), String> = map
+            .iter()
+            .map(|(k, v)| ((v.target_lang.clone(), v.source.clone()), k.clone()))
+            .collect();
+        let mut next = 0;
+        let mut keys = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let id = (entry.target_lang.clone(), entry.source.clone());
+            let key = match existing.get(&id) {
+                Some(key) => key.clone(),
+                None => {
+                    while map.contains_key(&next.to_string()) {
+                        next += 1;
+                    }
+                    let key = next.to_string();
+                    existing.insert(id, key.clone());
+                    key
+                }
+            };
+            map.insert(key.clone(), entry);
+            keys.push(key);
+        }
+        keys
+    })?;
+    db.save()?;
+    Ok(keys)
+}
+
+/// Reduce a language tag to its primary subtag, e.g. `zh-CN` to `zh`.
+fn primary_lang(lang: &str) -> String {
+    lang.split(['-', '_']).next().unwrap_or("").to_lowercase()
+}
+
+/// Parse translation units from a TMX document.
+pub fn parse_tmx(xml: &str) -> anyhow::Result<Vec<MemoryEntry>> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+
+    let mut entries = Vec::new();
+    // Variants of the current translation unit, as pairs of language and segment.
+    let mut variants: Vec<(String, String)> = Vec::new();
+    let mut lang = None;
+    let mut seg = None;
+
+    loop {
+        match reader.read_event().context("Cannot parse TMX")? {
+            Event::Start(e) if e.name().as_ref() == b"tu" => variants.clear(),
+            Event::Start(e) if e.name().as_ref() == b"tuv" => {
+                lang = None;
+                for attr in e.attributes() {
+                    let attr = attr?;
+                    if matches!(attr.key.as_ref(), b"xml:lang" | b"lang") {
+                        lang = Some(primary_lang(&attr.unescape_value()?));
+                    }
+                }
+            }
+            Event::Start(e) if e.name().as_ref() == b"seg" => seg = Some(String::new()),
+            Event::Text(e) => {
+                if let Some(seg) = &mut seg {
+                    seg.push_str(&e.unescape()?);
+                }
+            }
+            Event::CData(e) => {
+                if let Some(seg) = &mut seg {
+                    seg.push_str(std::str::from_utf8(&e)?);
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"seg" => {
+                if let (Some(lang), Some(seg)) = (lang.clone(), seg.take()) {
+                    variants.push((lang, seg));
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"tu" => {
+                let source = match variants.iter().find(|(lang, _)| lang == SOURCE_LANG) {
+                    Some((_, source)) => source.clone(),
+                    None => continue,
+                };
+                for (lang, target) in variants.drain(..) {
+                    if lang != SOURCE_LANG {
+                        entries.push(MemoryEntry {
+                            source: source.clone(),
+                            target,
+                            target_lang: lang,
+                        });
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Serialize the whole memory into a TMX document.
+pub fn to_tmx(db: &Database<String, MemoryEntry>) -> anyhow::Result<String> {
+    use quick_xml::escape::escape;
+
+    let mut entries: Vec<_> = db.iter()?.map(|x| x.value.clone()).collect();
+    entries.sort_by(|a, b| (&a.source, &a.target_lang).cmp(&(&b.source, &b.target_lang)));
+
+    let mut tmx = String::new();
+    tmx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    tmx.push_str("<tmx version=\"1.4\">\n");
+    tmx.push_str(&format!(
+        "  <header creationtool=\"ayt-translator\" creationtoolversion=\"{}\" datatype=\"plaintext\" segtype=\"sentence\" adminlang=\"en\" srclang=\"{}\" o-tmf=\"ayt\"/>\n",
+        env!("CARGO_PKG_VERSION"),
+        SOURCE_LANG,
+    ));
+    tmx.push_str("  <body>\n");
+    // Translations of the same source segment are grouped into a single translation unit.
+    for (i, entry) in entries.iter().enumerate() {
+        if i == 0 || entries[i - 1].source != entry.source {
+            tmx.push_str("    <tu>\n");
+            tmx.push_str(&format!(
+                "      <tuv xml:lang=\"{}\"><seg>{}</seg></tuv>\n",
+                SOURCE_LANG,
+                escape(&entry.source)
+            ));
+        }
+        tmx.push_str(&format!(
+            "      <tuv xml:lang=\"{}\"><seg>{}</seg></tuv>\n",
+            escape(&entry.target_lang),
+            escape(&entry.target)
+        ));
+        if entries.get(i + 1).map(|x| &x.source) != Some(&entry.source) {
+            tmx.push_str("    </tu>\n");
+        }
+    }
+    tmx.push_str("  </body>\n");
+    tmx.push_str("</tmx>\n");
+    Ok(tmx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(source: &str, target: &str, target_lang: &str) -> MemoryEntry {
+        MemoryEntry {
+            source: source.to_owned(),
+            target: target.to_owned(),
+            target_lang: target_lang.to_owned(),
+        }
+    }
+
+    fn memory(entries: Vec<MemoryEntry>) -> (tempfile::TempDir, Database<String, MemoryEntry>) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(dir.path().join("memory.db")).unwrap();
+        insert(&db, entries).unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn measures_similarity() {
+        let chars = |x: &str| x.chars().collect::<Vec<_>>();
+        assert_eq!(levenshtein(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(similarity(&chars(""), &chars("")), 1.0);
+        assert_eq!(similarity(&chars("abcd"), &chars("abce")), 0.75);
+    }
+
+    #[test]
+    fn finds_exact_and_fuzzy_matches() {
+        let (_dir, db) = memory(vec![
+            entry("おはようございます", "Good morning", "en"),
+            entry("おはようございます！", "Good morning!", "en"),
+            entry("おはようございます", "早上好", "zh"),
+            entry("こんばんは", "Good evening", "en"),
+        ]);
+
+        assert_eq!(
+            find_exact(&db, "zh", " おはようございます ")
+                .unwrap()
+                .as_deref(),
+            Some("早上好")
+        );
+        assert_eq!(find_exact(&db, "zh", "こんばんは").unwrap(), None);
+
+        // Exact matches are not suggested again, and unrelated segments are left out.
+        let suggestions = find_fuzzy(&db, "en", "おはようございます", 0.8, 5).unwrap();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].target, "Good morning!");
+        assert!(suggestions[0].score >= 0.8);
+    }
+
+    #[test]
+    fn replaces_existing_segments() {
+        let (_dir, db) = memory(vec![entry("すいせい", "Suisei", "en")]);
+        let keys = insert(
+            &db,
+            vec![
+                entry("すいせい", "Hoshimachi Suisei", "en"),
+                entry("みこ", "Miko", "en"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(keys, ["0", "1"]);
+        assert_eq!(
+            find_exact(&db, "en", "すいせい").unwrap().as_deref(),
+            Some("Hoshimachi Suisei")
+        );
+    }
+
+    #[test]
+    fn parses_tmx() {
+        let tmx = r#"<?xml version="1.0"?>
+<tmx version="1.4"><body>
+  <tu>
+    <tuv xml:lang="ja-JP"><seg>すいせい &amp; みこ</seg></tuv>
+    <tuv xml:lang="zh-CN"><seg><![CDATA[彗星 & 美子]]></seg></tuv>
+    <tuv lang="EN"><seg>Suisei &amp; Miko</seg></tuv>
+  </tu>
+  <tu><tuv xml:lang="en"><seg>No source</seg></tuv></tu>
+</body></tmx>"#;
+        let entries = parse_tmx(tmx).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].source, "すいせい & みこ");
+        assert_eq!(
+            (&*entries[0].target_lang, &*entries[0].target),
+            ("zh", "彗星 & 美子")
+        );
+        assert_eq!(
+            (&*entries[1].target_lang, &*entries[1].target),
+            ("en", "Suisei & Miko")
+        );
+
+        assert!(parse_tmx("<tmx><body><tu></tmx>").is_err());
+    }
+
+    #[test]
+    fn round_trips_tmx() {
+        let (_dir, db) = memory(vec![
+            entry("すいせい <3", "Suisei <3", "en"),
+            entry("すいせい <3", "彗星 <3", "zh"),
+            entry("\"みこ\"", "\"Miko\"", "en"),
+        ]);
+        let tmx = to_tmx(&db).unwrap();
+        assert_eq!(tmx.matches("<tu>").count(), 2);
+
+        let mut entries = parse_tmx(&tmx).unwrap();
+        entries.sort_by(|a, b| (&a.source, &a.target_lang).cmp(&(&b.source, &b.target_lang)));
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|x| (&*x.source, &*x.target, &*x.target_lang))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("\"みこ\"", "\"Miko\"", "en"),
+                ("すいせい <3", "Suisei <3", "en"),
+                ("すいせい <3", "彗星 <3", "zh"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Metrics exported in the Prometheus text format on `GET /metrics`.
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::time::Duration;
+
+use crate::usage::QuotaExceeded;
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ayt_http_requests_total",
+        "HTTP requests by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "ayt_http_request_duration_seconds",
+        "Time taken to respond to HTTP requests",
+        &["method", "route"]
+    )
+    .unwrap()
+});
+
+pub static PROVIDER_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ayt_provider_requests_total",
+        "Texts sent to machine translation providers",
+        &["provider"]
+    )
+    .unwrap()
+});
+
+pub static PROVIDER_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "ayt_provider_request_duration_seconds",
+        "Time taken by machine translation providers",
+        &["provider"],
+        vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+    )
+    .unwrap()
+});
+
+pub static PROVIDER_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ayt_provider_errors_total",
+        "Failed requests to machine translation providers by kind",
+        &["provider", "kind"]
+    )
+    .unwrap()
+});
+
+pub static PROVIDER_CHARACTERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ayt_provider_characters_total",
+        "Characters sent to machine translation providers",
+        &["provider"]
+    )
+    .unwrap()
+});
+
+pub static TERM_MATCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "ayt_term_matches_total",
+        "Matches of terms in translated texts by type",
+        &["type"]
+    )
+    .unwrap()
+});
+
+pub static DATABASE_ENTRIES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "ayt_database_entries",
+        "Number of entries in each database",
+        &["database"]
+    )
+    .unwrap()
+});
+
+pub static DATABASE_SAVE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "ayt_database_save_duration_seconds",
+        "Time taken to write databases to disk",
+        &["database"],
+        vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]
+    )
+    .unwrap()
+});
+
+/// Classify an error of a provider, so failures can be told apart from the outside.
+pub fn error_kind(err: &anyhow::Error) -> &'static str {
+    if err.downcast_ref::<QuotaExceeded>().is_some() {
+        return "quota";
+    }
+    #[cfg(feature = "http")]
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return match err {
+            err if err.is_timeout() => "timeout",
+            err if err.is_connect() => "connect",
+            err if err.is_decode() => "decode",
+            _ => "request",
+        };
+    }
+    // Errors returned by the provider itself, such as an invalid key.
+    "response"
+}
+
+/// Route of a request path, with ids replaced so the number of distinct labels stays small.
+pub fn route(path: &str, status: u16) -> &'static str {
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["api", "terms"] => "/api/terms",
+        ["api", "terms", "candidates"] => "/api/terms/candidates",
+        ["api", "terms", "stats"] => "/api/terms/stats",
+        ["api", "term"] => "/api/term",
+        ["api", "term", _] => "/api/term/:id",
+        ["api", "entities"] => "/api/entities",
+        ["api", "entity"] => "/api/entity",
+        ["api", "entity", _] => "/api/entity/:id",
+        ["api", "memory"] => "/api/memory",
+        ["api", "memory", "tmx"] => "/api/memory/tmx",
+        ["api", "memory", _] => "/api/memory/:id",
+        ["api", "usage"] => "/api/usage",
+        ["api", "feedback"] => "/api/feedback",
+        ["api", "feedback", "terms"] => "/api/feedback/terms",
+        ["api", "translate"] => "/api/translate",
+        ["api", "translate", "subtitles"] => "/api/translate/subtitles",
+        ["api", "chat"] => "/api/chat",
+        ["v2", "translate"] => "/v2/translate",
+        ["v2", "usage"] => "/v2/usage",
+        ["v2", "languages"] => "/v2/languages",
+        ["translate"] => "/translate",
+        ["languages"] => "/languages",
+        ["detect"] => "/detect",
+        ["metrics"] => "/metrics",
+        // Unknown paths would otherwise each get a label.
+        _ if status == 404 => "unmatched",
+        ["api", ..] | ["v2", ..] => "other",
+        _ => "static",
+    }
+}
+
+pub fn observe_request(method: &str, path: &str, status: u16, elapsed: Duration) {
+    let route = route(path, status);
+    HTTP_REQUESTS
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    HTTP_DURATION
+        .with_label_values(&[method, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Encode all metrics in the Prometheus text format.
+pub fn encode() -> anyhow::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_have_fixed_labels() {
+        assert_eq!(route("/api/term/123", 200), "/api/term/:id");
+        assert_eq!(route("/api/term/123", 404), "/api/term/:id");
+        assert_eq!(route("/api/memory/tmx", 200), "/api/memory/tmx");
+        assert_eq!(route("/v2/translate", 403), "/v2/translate");
+        assert_eq!(route("/v2/anything/123", 403), "other");
+        assert_eq!(route("/api/terms/123/x", 422), "other");
+        assert_eq!(route("/api/unknown", 404), "unmatched");
+        assert_eq!(route("/index.html", 200), "static");
+    }
+}
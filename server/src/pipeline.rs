@@ -0,0 +1,225 @@
+use regex::Regex;
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::db::Database;
+use crate::error::ApiError;
+use crate::memory::{self, MemoryEntry, Suggestion};
+use crate::runtime::Runtime;
+use crate::schema::{Entity, RegexTerm, TermType};
+use crate::stats::Stats;
+use crate::translator::{DictionaryTranslator, Normalizer, Translator};
+use crate::usage::QuotaExceeded;
+
+/// Translation of a text together with what was reused from the translation memory.
+#[derive(Serialize)]
+pub struct Translation {
+    pub translation: String,
+    /// Whether the translation is reused from the translation memory as a whole.
+    #[serde(rename = "fromMemory", skip_serializing_if = "std::ops::Not::not")]
+    pub from_memory: bool,
+    /// Approved translations of similar segments.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// A text to translate, with hints about how to translate it.
+#[derive(Default)]
+pub struct Request<'a> {
+    pub target_lang: &'a str,
+    pub text: &'a str,
+    /// Handle of the author, used to infer the context of the text.
+    pub author: Option<&'a str>,
+    /// Contexts the text is known to belong to, in addition to those inferred.
+    pub contexts: &'a [String],
+    /// Spans matching any of these patterns are left untouched.
+    pub protected: &'a [Regex],
+}
+
+/// Translates texts using the translation memory, terms and entities before falling back to the
+/// machine translator of the runtime.
+pub struct Pipeline {
+    pub terms: Arc<Database<String, RegexTerm>>,
+    pub entities: Arc<Database<String, Entity>>,
+    pub memory: Arc<Database<String, MemoryEntry>>,
+    /// Statistics of term matches. Not recorded if absent.
+    pub stats: Option<Arc<Stats>>,
+}
+
+/// Create a term that replaces a whole line with its approved translation.
+fn memory_term(
+    normalizer: Option<&Normalizer>,
+    source: &str,
+    target: String,
+    target_lang: &str,
+) -> anyhow::Result<RegexTerm> {
+    // Terms are matched against normalized input, so the line has to be normalized as well.
+    let normalized = normalizer.map(|x| x.normalize(source));
+    let source = match &normalized {
+        Some(normalized) => normalized.text().as_str(),
+        None => source,
+    };
+    Ok(RegexTerm {
+        input: fancy_regex::Regex::new(&format!(
+            r"(?m)^[ \t]*{}[ \t]*$",
+            regex::escape(source.trim())
+        ))?,
+        output: target,
+        target_lang: Some(target_lang.to_owned()),
+        translator: None,
+        priority: 0,
+        context: None,
+        ty: TermType::Transform,
+        comment: String::new(),
+        version: 0,
+        updated_at: 0,
+        id: None,
+    })
+}
+
+impl Pipeline {
+    pub async fn translate(
+        &self,
+        runtime: &Runtime,
+        request: Request<'_>,
+    ) -> anyhow::Result<Translation> {
+        let Request {
+            target_lang,
+            text,
+            author,
+            contexts,
+            ..
+        } = request;
+        let normalizer = runtime.normalizer.as_ref();
+        let translator = match runtime.translator(target_lang) {
+            Some(v) => v,
+            None => anyhow::bail!(ApiError::UnsupportedLanguage(target_lang.to_owned())),
+        };
+
+        if let Some(translation) = memory::find_exact(&self.memory, target_lang, text)? {
+            return Ok(Translation {
+                translation,
+                from_memory: true,
+                suggestions: Vec::new(),
+            });
+        }
+
+        // Reuse approved translations of individual lines, and suggest similar ones for the rest.
+        let threshold = runtime.config.memory_threshold;
+        let mut memory_terms = Vec::new();
+        let mut suggestions = memory::find_fuzzy(&self.memory, target_lang, text, threshold, 5)?;
+        let lines: Vec<_> = text.lines().filter(|x| !x.trim().is_empty()).collect();
+        if lines.len() > 1 {
+            for line in lines {
+                match memory::find_exact(&self.memory, target_lang, line)? {
+                    Some(target) => {
+                        memory_terms.push(memory_term(normalizer, line, target, target_lang)?)
+                    }
+                    None => suggestions.extend(memory::find_fuzzy(
+                        &self.memory,
+                        target_lang,
+                        line,
+                        threshold,
+                        5,
+                    )?),
+                }
+            }
+        }
+
+        let contexts = [contexts, &runtime.contexts.infer(author, text)].concat();
+        if !contexts.is_empty() {
+            log::info!("Inferred contexts: {:?}", contexts);
+        }
+
+        let mut entity_terms = Vec::new();
+        for entity in self.entities.iter()? {
+            entity_terms.extend(entity.value.terms(target_lang)?);
+        }
+
+        // Filtering out terms that shouldn't be applied in the specified context. Terms for other
+        // translators are filtered out once it is known which translator runs.
+        let mut eligible_terms: Vec<_> = self
+            .terms
+            .iter()?
+            .map(|v| (Some(v.key), v.value))
+            .chain(entity_terms.iter().map(|t| (None, t)))
+            .filter(|(_, t)| {
+                t.target_lang
+                    .as_ref()
+                    .map(|x| x == target_lang)
+                    .unwrap_or(true)
+            })
+            // Without any known context, terms of all contexts apply as they always did.
+            .filter(|(_, t)| {
+                t.context
+                    .as_ref()
+                    .map(|x| contexts.is_empty() || x.contains_any(&contexts))
+                    .unwrap_or(true)
+            })
+            .map(|(id, t)| RegexTerm {
+                id: id.map(|x| x.as_str().into()),
+                ..t.clone()
+            })
+            .collect();
+        eligible_terms.sort_unstable_by(RegexTerm::compare_priority);
+        // Lines from the translation memory take precedence over any other term.
+        eligible_terms.splice(0..0, memory_terms);
+
+        let descriptions = runtime.contexts.describe(&contexts);
+        let result = self
+            .translate_with(
+                runtime,
+                translator,
+                &request,
+                &eligible_terms,
+                &descriptions,
+            )
+            .await;
+        let result = match (result, translator.fallback()) {
+            (Err(err), Some(fallback)) if err.is::<QuotaExceeded>() => {
+                log::info!(
+                    "{} quota exhausted, using {}",
+                    translator.name(),
+                    fallback.name()
+                );
+                self.translate_with(runtime, fallback, &request, &eligible_terms, &descriptions)
+                    .await
+            }
+            (result, _) => result,
+        };
+        let translation = result.map_err(ApiError::upstream)?;
+        Ok(Translation {
+            translation,
+            from_memory: false,
+            suggestions,
+        })
+    }
+
+    /// Translate the text with the given translator, applying the terms meant for it.
+    async fn translate_with(
+        &self,
+        runtime: &Runtime,
+        translator: &dyn Translator,
+        request: &Request<'_>,
+        terms: &[RegexTerm],
+        descriptions: &[String],
+    ) -> anyhow::Result<String> {
+        let terms: Vec<_> = terms
+            .iter()
+            .filter(|t| {
+                t.translator
+                    .as_ref()
+                    .map(|x| x.contains(translator.name()))
+                    .unwrap_or(true)
+            })
+            .cloned()
+            .collect();
+        DictionaryTranslator::new(translator, &terms)
+            .with_normalizer(runtime.normalizer.as_ref())
+            .with_protected(request.protected)
+            .with_contexts(descriptions)
+            .with_stats(self.stats.as_deref(), request.target_lang)
+            .translate(request.text)
+            .await
+    }
+}
@@ -0,0 +1,372 @@
+//! Search, filtering, sorting and pagination of terms for `GET /api/terms`.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::error::ApiError;
+use crate::schema::{RegexTerm, TermType};
+use crate::stats::TermStats;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+enum SortField {
+    #[default]
+    Id,
+    Input,
+    Output,
+    TargetLang,
+    Type,
+    Priority,
+    Comment,
+    Matches,
+    LastMatched,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TermQuery {
+    /// Text to search for in the input, output and comment, ignoring case.
+    #[serde(default)]
+    q: Option<String>,
+    /// Interpret `q` as a regular expression instead.
+    #[serde(default)]
+    regex: bool,
+    /// Only terms of this target language, or `*` for terms of all languages.
+    #[serde(default)]
+    target_lang: Option<String>,
+    #[serde(default, rename = "type")]
+    ty: Option<TermType>,
+    /// Only terms applied when translating with this translator.
+    #[serde(default)]
+    translator: Option<String>,
+    /// Only terms applied to texts of this context.
+    #[serde(default)]
+    context: Option<String>,
+    #[serde(default)]
+    min_priority: Option<u32>,
+    #[serde(default)]
+    max_priority: Option<u32>,
+    /// Field to sort by, prefixed with `-` for descending order. Terms are sorted by id otherwise.
+    #[serde(default)]
+    sort: Option<String>,
+    /// Number of terms in a page. All terms are returned if unset.
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Position after which the page starts, as returned with the previous page.
+    #[serde(default)]
+    cursor: Option<String>,
+    /// Include statistics of matches in each term.
+    #[serde(default)]
+    pub stats: bool,
+}
+
+/// Value of the sorted field. All terms have the same variant for a given field.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+enum SortValue {
+    Number(u64),
+    Text(String),
+}
+
+/// Position of the last term of a page in the order of the query.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    value: SortValue,
+    id: String,
+}
+
+/// A page of terms matching a query.
+pub struct Page<'a> {
+    pub terms: Vec<(&'a String, &'a RegexTerm)>,
+    /// Number of terms matching the query, across all pages.
+    pub total: usize,
+    /// Cursor of the next page, if there is one.
+    pub next: Option<String>,
+}
+
+enum Search {
+    Text(String),
+    Regex(regex::Regex),
+}
+
+/// Order ids numerically where possible, as they are allocated from 0 upwards.
+fn id_order(id: &str) -> (Option<u64>, &str) {
+    (id.parse().ok(), id)
+}
+
+fn invalid(message: impl std::fmt::Display) -> ApiError {
+    ApiError::InvalidQuery(message.to_string())
+}
+
+impl TermQuery {
+    fn sort(&self) -> Result<(SortField, bool), ApiError> {
+        let sort = match self.sort.as_deref() {
+            None | Some("") => return Ok((SortField::default(), false)),
+            Some(v) => v,
+        };
+        let (name, descending) = match sort.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (sort, false),
+        };
+        let field = serde_json::from_value(serde_json::Value::String(name.to_owned()))
+            .map_err(|_| invalid(format_args!("Cannot sort by {}", name)))?;
+        Ok((field, descending))
+    }
+
+    /// Whether statistics of terms are needed to run the query.
+    pub fn needs_stats(&self) -> bool {
+        self.stats
+            || matches!(
+                self.sort(),
+                Ok((SortField::Matches | SortField::LastMatched, _))
+            )
+    }
+
+    fn search(&self) -> Result<Option<Search>, ApiError> {
+        Ok(match self.q.as_deref() {
+            None | Some("") => None,
+            Some(q) if self.regex => Some(Search::Regex(regex::Regex::new(q).map_err(invalid)?)),
+            Some(q) => Some(Search::Text(q.to_lowercase())),
+        })
+    }
+
+    fn matches(&self, term: &RegexTerm, search: Option<&Search>) -> bool {
+        let fields = [term.input.as_str(), &term.output, &term.comment];
+        let found = match search {
+            None => true,
+            Some(Search::Text(q)) => fields.iter().any(|x| x.to_lowercase().contains(q)),
+            Some(Search::Regex(regex)) => fields.iter().any(|x| regex.is_match(x)),
+        };
+        found
+            && match self.target_lang.as_deref() {
+                None => true,
+                Some("*") => term.target_lang.is_none(),
+                Some(lang) => term.target_lang.as_deref() == Some(lang),
+            }
+            && self.ty.is_none_or(|ty| term.ty == ty)
+            && self
+                .translator
+                .as_deref()
+                .is_none_or(|name| term.translator.as_ref().is_none_or(|x| x.contains(name)))
+            && self
+                .context
+                .as_deref()
+                .is_none_or(|context| term.context.as_ref().is_none_or(|x| x.contains(context)))
+            && self.min_priority.is_none_or(|x| term.priority >= x)
+            && self.max_priority.is_none_or(|x| term.priority <= x)
+    }
+
+    fn sort_value(
+        field: SortField,
+        id: &str,
+        term: &RegexTerm,
+        stats: &HashMap<String, TermStats>,
+    ) -> SortValue {
+        match field {
+            // Terms are ordered by id after the sorted field anyway.
+            SortField::Id => SortValue::Number(0),
+            SortField::Input => SortValue::Text(term.input.as_str().to_owned()),
+            SortField::Output => SortValue::Text(term.output.clone()),
+            SortField::TargetLang => SortValue::Text(term.target_lang.clone().unwrap_or_default()),
+            SortField::Type => SortValue::Number(term.ty as u64),
+            SortField::Priority => SortValue::Number(term.priority.into()),
+            SortField::Comment => SortValue::Text(term.comment.clone()),
+            SortField::Matches => SortValue::Number(stats.get(id).map_or(0, |x| x.count)),
+            SortField::LastMatched => {
+                SortValue::Number(stats.get(id).and_then(|x| x.last_matched).unwrap_or(0))
+            }
+        }
+    }
+
+    /// Find the page of terms matching the query.
+    ///
+    /// Terms with equal values of the sorted field are ordered by id, so pages are stable.
+    pub fn run<'a>(
+        &self,
+        terms: impl Iterator<Item = (&'a String, &'a RegexTerm)>,
+        stats: &HashMap<String, TermStats>,
+    ) -> Result<Page<'a>, ApiError> {
+        let (field, descending) = self.sort()?;
+        let search = self.search()?;
+        let cursor = match &self.cursor {
+            Some(cursor) => {
+                let json = URL_SAFE_NO_PAD
+                    .decode(cursor)
+                    .map_err(|_| invalid("Invalid cursor"))?;
+                let cursor: Cursor =
+                    serde_json::from_slice(&json).map_err(|_| invalid("Invalid cursor"))?;
+                Some(cursor)
+            }
+            None => None,
+        };
+
+        let compare = |a: (&SortValue, &str), b: (&SortValue, &str)| {
+            let ordering = a.0.cmp(b.0).then_with(|| id_order(a.1).cmp(&id_order(b.1)));
+            match descending {
+                true => ordering.reverse(),
+                false => ordering,
+            }
+        };
+
+        let mut matched: Vec<_> = terms
+            .filter(|(_, term)| self.matches(term, search.as_ref()))
+            .map(|(id, term)| (Self::sort_value(field, id, term, stats), id, term))
+            .collect();
+        matched.sort_by(|a, b| compare((&a.0, a.1), (&b.0, b.1)));
+        let total = matched.len();
+
+        let start = match &cursor {
+            Some(cursor) => matched.partition_point(|x| {
+                compare((&x.0, x.1), (&cursor.value, &cursor.id)) != Ordering::Greater
+            }),
+            None => 0,
+        };
+        let mut page = matched.split_off(start);
+        let next = match self.limit {
+            Some(limit) if page.len() > limit => {
+                page.truncate(limit);
+                page.last().map(|(value, id, _)| {
+                    let cursor = Cursor {
+                        value: value.clone(),
+                        id: id.to_string(),
+                    };
+                    URL_SAFE_NO_PAD.encode(serde_json::to_vec(&cursor).unwrap())
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Page {
+            terms: page.into_iter().map(|(_, id, term)| (id, term)).collect(),
+            total,
+            next,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms() -> Vec<(String, RegexTerm)> {
+        let terms = serde_json::json!({
+            "0": { "input": "すいせい", "output": "Suisei", "targetLang": "en", "priority": 2 },
+            "1": { "input": "みこち", "output": "Miko", "targetLang": "en", "comment": "nickname" },
+            "2": { "input": "ぺこら", "output": "Pekora", "priority": 2 },
+            "10": { "input": "こより", "output": "Koyori", "targetLang": "zh", "priority": 1 },
+        });
+        serde_json::from_value::<HashMap<String, RegexTerm>>(terms)
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    fn ids(page: &Page) -> Vec<String> {
+        page.terms.iter().map(|(id, _)| id.to_string()).collect()
+    }
+
+    fn run<'a>(
+        query: &TermQuery,
+        terms: &'a [(String, RegexTerm)],
+        stats: &HashMap<String, TermStats>,
+    ) -> Result<Page<'a>, ApiError> {
+        query.run(terms.iter().map(|(id, term)| (id, term)), stats)
+    }
+
+    #[test]
+    fn searches_and_filters() {
+        let terms = terms();
+        let stats = HashMap::new();
+        let search = |query: TermQuery| ids(&run(&query, &terms, &stats).unwrap());
+
+        let query = TermQuery {
+            q: Some("NICK".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(search(query), ["1"]);
+        let query = TermQuery {
+            q: Some("^(Suisei|Koyori)$".to_owned()),
+            regex: true,
+            ..Default::default()
+        };
+        assert_eq!(search(query), ["0", "10"]);
+        let query = TermQuery {
+            target_lang: Some("*".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(search(query), ["2"]);
+        let query = TermQuery {
+            target_lang: Some("en".to_owned()),
+            min_priority: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(search(query), ["0"]);
+
+        let query = TermQuery {
+            q: Some("(".to_owned()),
+            regex: true,
+            ..Default::default()
+        };
+        assert!(run(&query, &terms, &stats).is_err());
+    }
+
+    #[test]
+    fn sorts_by_field_then_id() {
+        let terms = terms();
+        let stats = HashMap::from([(
+            "1".to_owned(),
+            TermStats {
+                count: 5,
+                ..Default::default()
+            },
+        )]);
+        let sort = |sort: &str| {
+            let query = TermQuery {
+                sort: Some(sort.to_owned()),
+                ..Default::default()
+            };
+            ids(&run(&query, &terms, &stats).unwrap())
+        };
+
+        // Ids are ordered numerically, and in reverse for descending orders.
+        assert_eq!(sort(""), ["0", "1", "2", "10"]);
+        assert_eq!(sort("-priority"), ["2", "0", "10", "1"]);
+        assert_eq!(sort("output"), ["10", "1", "2", "0"]);
+        assert_eq!(sort("-matches"), ["1", "10", "2", "0"]);
+
+        let query = TermQuery {
+            sort: Some("unknown".to_owned()),
+            ..Default::default()
+        };
+        assert!(run(&query, &terms, &stats).is_err());
+    }
+
+    #[test]
+    fn paginates_with_cursor() {
+        let mut terms = terms();
+        let stats = HashMap::new();
+        let mut query = TermQuery {
+            sort: Some("-priority".to_owned()),
+            limit: Some(3),
+            ..Default::default()
+        };
+
+        let page = run(&query, &terms, &stats).unwrap();
+        assert_eq!(ids(&page), ["2", "0", "10"]);
+        assert_eq!(page.total, 4);
+        query.cursor = page.next;
+
+        // Pages continue after the cursor even if terms before it are removed.
+        terms.retain(|(id, _)| id != "0");
+        let page = run(&query, &terms, &stats).unwrap();
+        assert_eq!(ids(&page), ["1"]);
+        assert_eq!(page.total, 3);
+        assert!(page.next.is_none());
+
+        query.cursor = Some("invalid".to_owned());
+        assert!(run(&query, &terms, &stats).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwap;
+
+use crate::config::{self, Config};
+use crate::context::ContextRegistry;
+#[cfg(feature = "http")]
+use crate::translator::HttpClient;
+use crate::translator::{self, Normalizer, Translator};
+use crate::usage::Usage;
+#[cfg(feature = "http")]
+use std::collections::HashMap;
+
+/// Configuration together with everything built from it.
+///
+/// The whole runtime is replaced when the configuration is reloaded. Requests hold on to the
+/// runtime they started with, so in-flight translations finish with the old translators.
+pub struct Runtime {
+    pub config: Config,
+    pub translator_en: Box<dyn Translator>,
+    pub translator_zh: Box<dyn Translator>,
+    pub normalizer: Option<Normalizer>,
+    pub contexts: ContextRegistry,
+}
+
+/// Target languages with a translator in every runtime.
+pub const TARGET_LANGS: [&str; 2] = ["en", "zh"];
+
+/// Language of the texts given to translators.
+pub const SOURCE_LANG: &str = "ja";
+
+/// Builds translators of a configuration, sharing HTTP clients of a provider across languages.
+struct Loader<'a> {
+    config: &'a Config,
+    usage: &'a Arc<Usage>,
+    #[cfg(feature = "http")]
+    clients: HashMap<&'static str, Arc<HttpClient>>,
+    /// Started once and shared by all languages.
+    #[cfg(feature = "process")]
+    process: Option<Arc<translator::Process>>,
+}
+
+impl Loader<'_> {
+    #[cfg(feature = "http")]
+    fn http_client(
+        &mut self,
+        provider: &'static str,
+        config: &config::HttpConfig,
+    ) -> anyhow::Result<Arc<HttpClient>> {
+        if let Some(client) = self.clients.get(provider) {
+            return Ok(client.clone());
+        }
+        let client = Arc::new(HttpClient::new(config)?);
+        self.clients.insert(provider, client.clone());
+        Ok(client)
+    }
+
+    // Only the Nop translator is left without any provider.
+    #[cfg_attr(
+        not(any(feature = "http", feature = "process")),
+        allow(unused_variables)
+    )]
+    fn translator(
+        &mut self,
+        target_lang: &str,
+        translator: config::Translator,
+    ) -> anyhow::Result<Box<dyn Translator>> {
+        let config = self.config;
+        Ok(match translator {
+            config::Translator::Nop => Box::new(translator::NopTranslator),
+            #[cfg(feature = "google")]
+            config::Translator::Google => Box::new(translator::GoogleTranslator::new(
+                self.http_client("google", &config.google.http)?,
+                config.google.base_url.clone(),
+                target_lang.to_owned(),
+            )),
+            #[cfg(feature = "baidu")]
+            config::Translator::Baidu => {
+                let config = config
+                    .baidu
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Baidu config not found"))?;
+                Box::new(translator::BaiduTranslator::new(
+                    self.http_client("baidu", &config.http)?,
+                    config.base_url.clone(),
+                    config.appid.clone(),
+                    config.secret.clone(),
+                    target_lang.to_owned(),
+                ))
+            }
+            #[cfg(feature = "microsoft")]
+            config::Translator::Microsoft => {
+                let config = config
+                    .microsoft
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Microsoft config not found"))?;
+                Box::new(translator::MicrosoftTranslator::new(
+                    self.http_client("microsoft", &config.http)?,
+                    config.base_url.clone(),
+                    config.api_key.clone(),
+                    target_lang.to_owned(),
+                ))
+            }
+            #[cfg(feature = "deepl")]
+            config::Translator::DeepL => {
+                let config = config
+                    .deepl
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("DeepL config not found"))?;
+                Box::new(translator::DeepLTranslator::new(
+                    self.http_client("deepl", &config.http)?,
+                    config.base_url.clone(),
+                    config.auth_key.clone(),
+                    target_lang.to_owned(),
+                ))
+            }
+            #[cfg(feature = "llm")]
+            config::Translator::Llm => {
+                let config = config
+                    .llm
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("LLM config not found"))?;
+                Box::new(translator::LlmTranslator::new(
+                    self.http_client("llm", &config.http)?,
+                    config,
+                    target_lang.to_owned(),
+                ))
+            }
+            #[cfg(feature = "libretranslate")]
+            config::Translator::LibreTranslate => {
+                let config = config
+                    .libretranslate
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("LibreTranslate config not found"))?;
+                Box::new(translator::LibreTranslateTranslator::new(
+                    self.http_client("libretranslate", &config.http)?,
+                    &config.url,
+                    config.api_key.clone(),
+                    config.source.clone(),
+                    target_lang.to_owned(),
+                ))
+            }
+            #[cfg(feature = "generic")]
+            config::Translator::Generic => {
+                let config = config
+                    .generic
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Generic translator config not found"))?;
+                Box::new(translator::GenericTranslator::new(
+                    self.http_client("generic", &config.http)?,
+                    config,
+                    target_lang,
+                )?)
+            }
+            #[cfg(feature = "process")]
+            config::Translator::Process => {
+                let config = config
+                    .process
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Process config not found"))?;
+                let process = self
+                    .process
+                    .get_or_insert_with(|| Arc::new(translator::Process::new(config)))
+                    .clone();
+                Box::new(translator::ProcessTranslator::new(
+                    process,
+                    config.source.clone(),
+                    target_lang.to_owned(),
+                ))
+            }
+        })
+    }
+
+    /// Load a translator that keeps track of characters sent to it and enforces its monthly quota.
+    fn metered_translator(
+        &mut self,
+        target_lang: &str,
+        translator: config::Translator,
+        allow_fallback: bool,
+    ) -> anyhow::Result<Box<dyn Translator>> {
+        let inner = self.translator(target_lang, translator)?;
+        if matches!(translator, config::Translator::Nop) {
+            return Ok(inner);
+        }
+
+        let quota = self.config.quota.get(&inner.name().to_lowercase());
+        let fallback = match quota.and_then(|x| x.fallback) {
+            // Fallbacks do not chain, to avoid cycles between translators.
+            Some(fallback) if allow_fallback => {
+                Some(self.metered_translator(target_lang, fallback, false)?)
+            }
+            _ => None,
+        };
+        Ok(Box::new(translator::QuotaTranslator::new(
+            inner,
+            self.usage.clone(),
+            quota.and_then(|x| x.monthly),
+            quota.is_some_and(|x| x.sync),
+            fallback,
+        )))
+    }
+}
+
+impl Runtime {
+    pub fn new(config: Config, usage: &Arc<Usage>) -> anyhow::Result<Self> {
+        config.validate()?;
+
+        let mut loader = Loader {
+            config: &config,
+            usage,
+            #[cfg(feature = "http")]
+            clients: HashMap::new(),
+            #[cfg(feature = "process")]
+            process: None,
+        };
+        let translator_en = loader.metered_translator("en", config.en, true)?;
+        let translator_zh = loader.metered_translator("zh", config.zh, true)?;
+        let normalizer = config
+            .normalize
+            .as_ref()
+            .map(|config| Normalizer::new(config.laughter.clone()));
+        let contexts = ContextRegistry::load(&config.contexts, &config.authors)?;
+
+        Ok(Self {
+            config,
+            translator_en,
+            translator_zh,
+            normalizer,
+            contexts,
+        })
+    }
+
+    /// Translator for the given target language, if supported.
+    pub fn translator(&self, target_lang: &str) -> Option<&dyn Translator> {
+        match target_lang {
+            "en" => Some(&*self.translator_en),
+            "zh" => Some(&*self.translator_zh),
+            _ => None,
+        }
+    }
+}
+
+/// Swappable handle to the current runtime.
+pub struct Handle {
+    path: PathBuf,
+    usage: Arc<Usage>,
+    current: ArcSwap<Runtime>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+impl Handle {
+    pub fn new(path: PathBuf, usage: Arc<Usage>, runtime: Runtime) -> Self {
+        Self {
+            path,
+            usage,
+            current: ArcSwap::from_pointee(runtime),
+        }
+    }
+
+    pub fn load(&self) -> Arc<Runtime> {
+        self.current.load_full()
+    }
+
+    /// Reload the configuration file, keeping the current runtime if the new one is invalid.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let config = Config::load(&self.path)?;
+        let runtime = Runtime::new(config, &self.usage)?;
+
+        let (old, new) = (&self.current.load().config, &runtime.config);
+        for (name, changed) in [
+            ("listen", old.listen != new.listen),
+            ("database", old.database != new.database),
+            ("entities", old.entities != new.entities),
+            ("memory", old.memory != new.memory),
+            ("feedback", old.feedback != new.feedback),
+            ("usage", old.usage != new.usage),
+            ("traffic", old.traffic != new.traffic),
+        ] {
+            if changed {
+                log::warn!("Change of `{}` takes effect after restart", name);
+            }
+        }
+
+        self.current.store(Arc::new(runtime));
+        log::info!("Reloaded {}", self.path.display());
+        Ok(())
+    }
+
+    /// Reload the configuration on SIGHUP or when the file is modified.
+    pub async fn watch(self: Arc<Self>) {
+        let reload = || {
+            if let Err(err) = self.reload() {
+                log::error!("Keeping current configuration: {:?}", err);
+            }
+        };
+
+        #[cfg(unix)]
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        {
+            Ok(v) => Some(v),
+            Err(err) => {
+                log::warn!("Cannot listen for SIGHUP: {:?}", err);
+                None
+            }
+        };
+
+        let mut last_modified = modified(&self.path);
+        let mut interval = tokio::time::interval(Duration::from_secs(2));
+        loop {
+            #[cfg(unix)]
+            let hangup = async {
+                match &mut hangup {
+                    Some(hangup) => hangup.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+
+            tokio::select! {
+                _ = hangup => {
+                    last_modified = modified(&self.path);
+                    reload();
+                }
+                _ = interval.tick() => {
+                    let modified = modified(&self.path);
+                    if modified != last_modified {
+                        last_modified = modified;
+                        reload();
+                    }
+                }
+            }
+        }
+    }
+}
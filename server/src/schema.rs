@@ -1,19 +1,26 @@
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 use fancy_regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum TermType {
     Preprocess,
+    #[default]
     Transform,
     Postprocess,
 }
 
-impl Default for TermType {
-    fn default() -> Self {
-        TermType::Transform
+impl TermType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TermType::Preprocess => "preprocess",
+            TermType::Transform => "transform",
+            TermType::Postprocess => "postprocess",
+        }
     }
 }
 
@@ -24,9 +31,28 @@ pub struct FilterList {
 }
 
 impl FilterList {
+    pub fn new(exclude: bool, list: Vec<String>) -> Self {
+        Self { exclude, list }
+    }
+
     pub fn contains(&self, value: &str) -> bool {
         self.list.iter().any(|x| x == value) ^ self.exclude
     }
+
+    /// Check against a set of values: any of them must be listed, or none if this is an exclusion.
+    pub fn contains_any(&self, values: &[String]) -> bool {
+        values.iter().any(|x| self.list.contains(x)) ^ self.exclude
+    }
+}
+
+/// Comma separated values, prefixed with `!` if they are excluded.
+impl std::fmt::Display for FilterList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.exclude {
+            f.write_str("!")?;
+        }
+        f.write_str(&self.list.join(","))
+    }
 }
 
 fn is_default<T: Default + Eq>(value: &T) -> bool {
@@ -74,9 +100,28 @@ pub struct RegexTerm {
     pub ty: TermType,
     #[serde(default, skip_serializing_if = "String::is_empty")]
     pub comment: String,
+    /// Incremented whenever the term is changed, to detect concurrent edits.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub version: u64,
+    /// Time the term was last changed, in seconds since the Unix epoch.
+    #[serde(rename = "updatedAt", default, skip_serializing_if = "is_default")]
+    pub updated_at: u64,
+    /// Id of the term in the dictionary, set while translating to record statistics of matches.
+    ///
+    /// Terms generated from entities or the translation memory have none.
+    #[serde(skip)]
+    pub id: Option<Arc<str>>,
 }
 
 impl RegexTerm {
+    /// Mark the term as the next version of `previous`, or as a new term if there is none.
+    pub fn touch(&mut self, previous: Option<&RegexTerm>) {
+        self.version = previous.map_or(0, |x| x.version) + 1;
+        self.updated_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+    }
+
     pub fn compare_priority(&self, other: &Self) -> Ordering {
         if self.priority != other.priority {
             return self.priority.cmp(&other.priority);
@@ -93,3 +138,157 @@ impl RegexTerm {
         self.input.as_str().len().cmp(&other.input.as_str().len())
     }
 }
+
+/// Honorific that may follow a name, with its rendering in each target language.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Honorific {
+    pub input: String,
+    /// Rendering of the honorific keyed by target language.
+    ///
+    /// An empty string drops the honorific. Languages missing here leave the honorific untouched.
+    pub output: BTreeMap<String, String>,
+}
+
+/// A named entity, e.g. a member, from which terms are generated automatically.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entity {
+    /// Names used in the source text, such as full name, given name and nicknames.
+    pub aliases: Vec<String>,
+    /// Rendering of the name keyed by target language.
+    pub renderings: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub honorifics: Vec<Honorific>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translator: Option<FilterList>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub priority: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<FilterList>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+}
+
+impl Entity {
+    fn term(&self, input: String, output: String, target_lang: &str) -> anyhow::Result<RegexTerm> {
+        Ok(RegexTerm {
+            input: Regex::new(&input)?,
+            output,
+            target_lang: Some(target_lang.to_owned()),
+            translator: self.translator.clone(),
+            priority: self.priority,
+            context: self.context.clone(),
+            ty: TermType::Transform,
+            comment: self.comment.clone(),
+            version: 0,
+            updated_at: 0,
+            id: None,
+        })
+    }
+
+    /// Generate terms for the given target language.
+    pub fn terms(&self, target_lang: &str) -> anyhow::Result<Vec<RegexTerm>> {
+        let rendering = match self.renderings.get(target_lang) {
+            Some(v) => v,
+            None => return Ok(Vec::new()),
+        };
+
+        if self.aliases.iter().all(String::is_empty) {
+            anyhow::bail!("Entity has no aliases");
+        }
+
+        // Prefer the longest alias, so full names are not split into given names.
+        let mut aliases: Vec<_> = self.aliases.iter().filter(|x| !x.is_empty()).collect();
+        aliases.sort_unstable_by_key(|x| std::cmp::Reverse(x.len()));
+        let names = aliases
+            .iter()
+            .map(|x| regex::escape(x))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        let mut terms = Vec::new();
+        let mut honorifics = Vec::new();
+        for honorific in &self.honorifics {
+            let output = match honorific.output.get(target_lang) {
+                Some(v) => v,
+                None => continue,
+            };
+            let input = regex::escape(&honorific.input);
+            terms.push(self.term(
+                format!("(?:{}){}", names, input),
+                format!("{}{}", rendering, output),
+                target_lang,
+            )?);
+            honorifics.push(input);
+        }
+
+        // The bare name must not consume names that are followed by a known honorific.
+        let input = if honorifics.is_empty() {
+            format!("(?:{})", names)
+        } else {
+            format!("(?:{})(?!{})", names, honorifics.join("|"))
+        };
+        terms.push(self.term(input, rendering.clone(), target_lang)?);
+        Ok(terms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn suisei() -> Entity {
+        serde_json::from_value(json!({
+            "aliases": ["すいせい", "星街すいせい", ""],
+            "renderings": { "en": "Suisei", "zh": "彗星" },
+            "honorifics": [
+                { "input": "ちゃん", "output": { "en": "-chan", "zh": "酱" } },
+                { "input": "先輩", "output": { "en": "" } },
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn apply(terms: &[RegexTerm], text: &str) -> Option<String> {
+        terms.iter().find_map(|term| {
+            let found = term.input.find(text).unwrap()?;
+            Some(term.output.clone()).filter(|_| found.as_str().len() == text.len())
+        })
+    }
+
+    #[test]
+    fn generates_names_with_honorifics() {
+        let terms = suisei().terms("en").unwrap();
+        assert_eq!(terms.len(), 3);
+        assert!(terms.iter().all(|x| x.target_lang.as_deref() == Some("en")));
+
+        assert_eq!(
+            apply(&terms, "すいせいちゃん").as_deref(),
+            Some("Suisei-chan")
+        );
+        assert_eq!(apply(&terms, "星街すいせい先輩").as_deref(), Some("Suisei"));
+        assert_eq!(apply(&terms, "星街すいせい").as_deref(), Some("Suisei"));
+        // The bare name leaves names with a known honorific to the terms including it.
+        let bare = terms.last().unwrap();
+        assert!(!bare.input.is_match("すいせいちゃん").unwrap());
+        assert!(bare.input.is_match("すいせいさん").unwrap());
+    }
+
+    #[test]
+    fn leaves_honorifics_without_rendering() {
+        let terms = suisei().terms("zh").unwrap();
+        // 先輩 has no rendering in Chinese, so it is not excluded from the bare name.
+        assert_eq!(terms.len(), 2);
+        assert_eq!(apply(&terms, "すいせいちゃん").as_deref(), Some("彗星酱"));
+        assert!(terms[1].input.is_match("すいせい先輩").unwrap());
+    }
+
+    #[test]
+    fn skips_languages_without_rendering() {
+        assert!(suisei().terms("ko").unwrap().is_empty());
+
+        let mut entity = suisei();
+        entity.aliases = vec![String::new()];
+        assert!(entity.terms("en").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLockReadGuard};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::db::Database;
+
+/// Matches are collected for at most this long before they are written to disk.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Matches waiting to be written. Matches are dropped rather than slowing down translation when
+/// full.
+const QUEUE_SIZE: usize = 10_000;
+
+/// How often a term of the dictionary has matched, keyed by the id of the term.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TermStats {
+    pub count: u64,
+    /// Time the term last matched, in seconds since the Unix epoch.
+    #[serde(
+        rename = "lastMatched",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_matched: Option<u64>,
+    /// Matches keyed by target language.
+    pub languages: BTreeMap<String, u64>,
+}
+
+struct Match {
+    id: Arc<str>,
+    target_lang: String,
+    time: u64,
+}
+
+/// Persistent statistics of term matches.
+///
+/// Matches are recorded without waiting, and saved in batches by a background task.
+pub struct Stats {
+    db: Arc<Database<String, TermStats>>,
+    sender: mpsc::Sender<Match>,
+    receiver: Arc<Mutex<mpsc::Receiver<Match>>>,
+}
+
+impl Stats {
+    /// Open the database and start saving recorded matches. Must be called within the runtime.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let db = Arc::new(Database::open(path)?);
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (task_db, task_receiver) = (db.clone(), receiver.clone());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(err) = Self::flush(&task_db, &task_receiver) {
+                    log::error!("Cannot save term stats: {:?}", err);
+                }
+            }
+        });
+        Ok(Self {
+            db,
+            sender,
+            receiver,
+        })
+    }
+
+    /// Save the matches waiting in the queue.
+    fn flush(
+        db: &Database<String, TermStats>,
+        receiver: &Mutex<mpsc::Receiver<Match>>,
+    ) -> anyhow::Result<()> {
+        // The queue stays locked until matches are saved, so a concurrent flush does not return
+        // before them.
+        let mut receiver = receiver.lock().unwrap();
+        let matches: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok()).collect();
+        if matches.is_empty() {
+            return Ok(());
+        }
+        db.db.write(|map| Self::apply(map, matches))?;
+        db.save()
+    }
+
+    /// Save recorded matches right away.
+    pub fn save(&self) -> anyhow::Result<()> {
+        Self::flush(&self.db, &self.receiver)
+    }
+
+    fn apply(map: &mut HashMap<String, TermStats>, matches: Vec<Match>) {
+        for item in matches {
+            let stats = map.entry(item.id.to_string()).or_default();
+            stats.count += 1;
+            stats.last_matched = stats.last_matched.max(Some(item.time));
+            *stats.languages.entry(item.target_lang).or_default() += 1;
+        }
+    }
+
+    /// Record a match of the term with the given id.
+    pub fn record(&self, id: &Arc<str>, target_lang: &str) {
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        let item = Match {
+            id: id.clone(),
+            target_lang: target_lang.to_owned(),
+            time,
+        };
+        if self.sender.try_send(item).is_err() {
+            log::warn!("Term stats queue is full, dropping match");
+        }
+    }
+
+    /// Statistics of all terms that have matched at least once.
+    ///
+    /// Saving recorded matches waits for the guard to be dropped.
+    pub fn all(&self) -> anyhow::Result<RwLockReadGuard<'_, HashMap<String, TermStats>>> {
+        Ok(self.db.db.borrow_data()?)
+    }
+
+    /// Forget the statistics of a removed term, so they are not inherited by a term reusing its id.
+    pub fn remove(&self, id: &str) -> anyhow::Result<()> {
+        // Matches waiting in the queue would otherwise bring the statistics back.
+        let mut receiver = self.receiver.lock().unwrap();
+        let matches: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok()).collect();
+        let pending = !matches.is_empty();
+        let removed = self.db.db.write(|map| {
+            Self::apply(map, matches.into_iter().filter(|x| &*x.id != id).collect());
+            map.remove(id).is_some()
+        })?;
+        if pending || removed {
+            self.db.save()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn forgets_removed_terms() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = Stats::open(dir.path().join("term_stats.db")).unwrap();
+        let (a, b): (Arc<str>, Arc<str>) = ("0".into(), "1".into());
+        stats.record(&a, "en");
+        stats.record(&b, "en");
+        stats.save().unwrap();
+        stats.record(&a, "zh");
+
+        // The match still waiting in the queue is dropped too.
+        stats.remove("0").unwrap();
+        stats.record(&b, "zh");
+        stats.save().unwrap();
+        let all = stats.all().unwrap();
+        assert!(!all.contains_key("0"));
+        assert_eq!(all["1"].count, 2);
+        assert_eq!(all["1"].languages["zh"], 1);
+    }
+}
@@ -0,0 +1,365 @@
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::pipeline::{Pipeline, Request};
+use crate::runtime::Runtime;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Srt,
+    Vtt,
+    Ass,
+}
+
+/// Line break within a cue while it is being translated. This is also the line break of ASS.
+const LINE_BREAK: &str = r"\N";
+
+static HTML_TAG_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"</?[a-zA-Z0-9.:_\-]+[^>]*>").unwrap());
+static VTT_TIMESTAMP_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"<\d+(?::\d+)+\.\d+>").unwrap());
+static ENTITY_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"&(?:[a-z]+|#\d+);").unwrap());
+static OVERRIDE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{[^{}]*\}").unwrap());
+static ASS_ESCAPE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\[Nnh]").unwrap());
+
+impl Format {
+    /// Guess the format from the content of a file, defaulting to SRT.
+    pub fn detect(text: &str) -> Self {
+        let text = text.trim_start_matches('\u{FEFF}').trim_start();
+        if text.starts_with("WEBVTT") {
+            Format::Vtt
+        } else if text.starts_with("[Script Info]") {
+            Format::Ass
+        } else {
+            Format::Srt
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Srt => "application/x-subrip",
+            Format::Vtt => "text/vtt",
+            Format::Ass => "text/x-ssa",
+        }
+    }
+
+    /// Markup within cue text that must be kept out of translation.
+    fn protected(self) -> Vec<Regex> {
+        let patterns: &[&Lazy<Regex>] = match self {
+            // SRT files in the wild use both HTML-like tags and ASS override blocks.
+            Format::Srt => &[&OVERRIDE_REGEX, &HTML_TAG_REGEX, &ASS_ESCAPE_REGEX],
+            Format::Vtt => &[
+                &VTT_TIMESTAMP_REGEX,
+                &HTML_TAG_REGEX,
+                &ENTITY_REGEX,
+                &ASS_ESCAPE_REGEX,
+            ],
+            Format::Ass => &[&OVERRIDE_REGEX, &ASS_ESCAPE_REGEX],
+        };
+        patterns.iter().map(|x| Regex::clone(x)).collect()
+    }
+}
+
+enum Segment {
+    /// Written out unchanged, including any line breaks.
+    Raw(String),
+    /// Text of a cue, with lines separated by `\n`, or a single line for ASS.
+    Cue(String),
+}
+
+/// A parsed subtitle file in which only the text of cues can be changed.
+pub struct Subtitle {
+    format: Format,
+    newline: &'static str,
+    segments: Vec<Segment>,
+    trailing_newline: bool,
+}
+
+impl Subtitle {
+    pub fn parse(format: Format, text: &str) -> Self {
+        let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut subtitle = Subtitle {
+            format,
+            newline,
+            segments: Vec::new(),
+            trailing_newline: text.ends_with('\n'),
+        };
+        let lines: Vec<_> = text.lines().collect();
+        match format {
+            Format::Srt | Format::Vtt => subtitle.parse_cues(&lines),
+            Format::Ass => subtitle.parse_events(&lines),
+        }
+        subtitle
+    }
+
+    fn raw(&mut self, text: &str) {
+        match self.segments.last_mut() {
+            Some(Segment::Raw(raw)) => raw.push_str(text),
+            _ => self.segments.push(Segment::Raw(text.to_owned())),
+        }
+    }
+
+    /// Parse SRT and WebVTT, where the text of a cue follows its timing line until a blank line.
+    fn parse_cues(&mut self, lines: &[&str]) {
+        let mut lines = lines.iter().peekable();
+        while let Some(line) = lines.next() {
+            self.raw(line);
+            self.raw(self.newline);
+            if !line.contains("-->") {
+                continue;
+            }
+
+            let mut cue = Vec::new();
+            while let Some(line) = lines.next_if(|x| !x.trim().is_empty()) {
+                cue.push(*line);
+            }
+            if !cue.is_empty() {
+                self.segments.push(Segment::Cue(cue.join("\n")));
+                self.raw(self.newline);
+            }
+        }
+    }
+
+    /// Parse ASS, where the text is the last field of `Dialogue` lines in the `[Events]` section.
+    fn parse_events(&mut self, lines: &[&str]) {
+        let mut in_events = false;
+        let mut fields = 10;
+        for line in lines {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') {
+                in_events = trimmed.eq_ignore_ascii_case("[Events]");
+            } else if in_events && trimmed.starts_with("Format:") {
+                fields = trimmed.split(',').count();
+            } else if in_events && trimmed.starts_with("Dialogue:") {
+                // The text may contain commas itself, so only split off the fields before it.
+                // Lines of a malformed format without any field before the text are kept as is.
+                let text_start = fields
+                    .checked_sub(2)
+                    .and_then(|n| line.match_indices(',').nth(n));
+                if let Some((start, _)) = text_start {
+                    self.raw(&line[..start + 1]);
+                    self.segments
+                        .push(Segment::Cue(line[start + 1..].to_owned()));
+                    self.raw(self.newline);
+                    continue;
+                }
+            }
+            self.raw(line);
+            self.raw(self.newline);
+        }
+    }
+
+    /// Write the subtitle back in its original format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Raw(raw) => out.push_str(raw),
+                Segment::Cue(cue) => out.push_str(&cue.replace('\n', self.newline)),
+            }
+        }
+        if !self.trailing_newline && out.ends_with(self.newline) {
+            out.truncate(out.len() - self.newline.len());
+        }
+        out
+    }
+
+    /// Cue text as a single line, so cues can be joined into a batch line by line.
+    fn encode(&self, cue: &str) -> String {
+        match self.format {
+            Format::Ass => cue.to_owned(),
+            _ => cue.replace('\n', LINE_BREAK),
+        }
+    }
+
+    fn decode(&self, translation: &str) -> String {
+        let translation = translation.trim().replace('\n', LINE_BREAK);
+        match self.format {
+            Format::Ass => translation,
+            _ => translation.replace(LINE_BREAK, "\n"),
+        }
+    }
+
+    /// Translate the text of all cues.
+    ///
+    /// Neighboring cues are translated together in batches, so the translator sees them in
+    /// context.
+    pub async fn translate(
+        &mut self,
+        pipeline: &Pipeline,
+        runtime: &Runtime,
+        target_lang: &str,
+        author: Option<&str>,
+        batch: usize,
+    ) -> anyhow::Result<()> {
+        let protected = self.format.protected();
+        let cues: Vec<(usize, String)> = self
+            .segments
+            .iter()
+            .enumerate()
+            .filter_map(|(i, segment)| match segment {
+                Segment::Cue(cue) if !cue.trim().is_empty() => Some((i, self.encode(cue))),
+                _ => None,
+            })
+            .collect();
+
+        let batches: Vec<_> = cues
+            .chunks(batch.max(1))
+            .map(|chunk| translate_batch(pipeline, runtime, target_lang, author, &protected, chunk))
+            .collect();
+        let batches: Vec<Vec<String>> = futures::stream::iter(batches)
+            .buffered(4)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<anyhow::Result<_>>()?;
+
+        for ((i, _), translation) in cues.iter().zip(batches.into_iter().flatten()) {
+            self.segments[*i] = Segment::Cue(self.decode(&translation));
+        }
+        Ok(())
+    }
+}
+
+/// Translate neighboring cues as lines of one text, or cue by cue if the translator does not keep
+/// the lines apart.
+///
+/// In the latter case the cues are sent to the provider twice, and billed twice. Batches of a
+/// single cue avoid this for translators that often merge lines.
+async fn translate_batch(
+    pipeline: &Pipeline,
+    runtime: &Runtime,
+    target_lang: &str,
+    author: Option<&str>,
+    protected: &[Regex],
+    cues: &[(usize, String)],
+) -> anyhow::Result<Vec<String>> {
+    let request = |text| Request {
+        target_lang,
+        text,
+        author,
+        protected,
+        ..Default::default()
+    };
+    let joined = cues
+        .iter()
+        .map(|(_, cue)| cue.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let translation = pipeline
+        .translate(runtime, request(&joined))
+        .await?
+        .translation;
+    let lines: Vec<_> = translation.lines().collect();
+    if lines.len() == cues.len() {
+        return Ok(lines.into_iter().map(str::to_owned).collect());
+    }
+
+    log::warn!(
+        "Translator merged {} cues into {} lines, translating cue by cue",
+        cues.len(),
+        lines.len()
+    );
+    let mut translations = Vec::with_capacity(cues.len());
+    for (_, cue) in cues {
+        let translation = pipeline.translate(runtime, request(cue)).await?;
+        translations.push(translation.translation);
+    }
+    Ok(translations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cues(subtitle: &Subtitle) -> Vec<&str> {
+        subtitle
+            .segments
+            .iter()
+            .filter_map(|x| match x {
+                Segment::Cue(cue) => Some(cue.as_str()),
+                Segment::Raw(_) => None,
+            })
+            .collect()
+    }
+
+    fn translate_cues(subtitle: &mut Subtitle, f: impl Fn(&str) -> String) {
+        for segment in &mut subtitle.segments {
+            if let Segment::Cue(cue) = segment {
+                *cue = f(cue);
+            }
+        }
+    }
+
+    #[test]
+    fn detects_format() {
+        assert_eq!(Format::detect("\u{FEFF}WEBVTT\n\n"), Format::Vtt);
+        assert_eq!(Format::detect("[Script Info]\nTitle: x\n"), Format::Ass);
+        assert_eq!(
+            Format::detect("1\n00:00:01,000 --> 00:00:02,000\n"),
+            Format::Srt
+        );
+    }
+
+    #[test]
+    fn parses_and_renders_srt() {
+        let text = "1\r\n00:00:01,000 --> 00:00:02,000\r\nこんにちは\r\n<i>みなさん</i>\r\n\r\n\
+                    2\r\n00:00:03,000 --> 00:00:04,000\r\nおつかれ\r\n";
+        let mut subtitle = Subtitle::parse(Format::Srt, text);
+        assert_eq!(cues(&subtitle), ["こんにちは\n<i>みなさん</i>", "おつかれ"]);
+        assert_eq!(subtitle.render(), text);
+
+        translate_cues(&mut subtitle, |cue| cue.replace("おつかれ", "Good work"));
+        assert!(subtitle
+            .render()
+            .ends_with("00:00:03,000 --> 00:00:04,000\r\nGood work\r\n"));
+    }
+
+    #[test]
+    fn parses_and_renders_vtt() {
+        let text = "WEBVTT\n\nNOTE comment\n\n00:01.000 --> 00:02.000 align:start\n\
+                    <v Suisei>すいちゃんは<00:01.500>今日も</v>\n\n00:03.000 --> 00:04.000\n\n";
+        let subtitle = Subtitle::parse(Format::Vtt, text);
+        assert_eq!(
+            cues(&subtitle),
+            ["<v Suisei>すいちゃんは<00:01.500>今日も</v>"]
+        );
+        assert_eq!(subtitle.render(), text);
+    }
+
+    #[test]
+    fn parses_and_renders_ass() {
+        let text = "[Script Info]\nTitle: test\n\n[Events]\n\
+                    Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+                    Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\i1}かわいい、{\\i0}すごい\\Nね\n";
+        let mut subtitle = Subtitle::parse(Format::Ass, text);
+        assert_eq!(cues(&subtitle), ["{\\i1}かわいい、{\\i0}すごい\\Nね"]);
+        assert_eq!(subtitle.render(), text);
+
+        translate_cues(&mut subtitle, |_| "Cute, amazing".to_owned());
+        assert!(subtitle
+            .render()
+            .ends_with("Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,Cute, amazing\n"));
+    }
+
+    #[test]
+    fn keeps_dialogue_of_malformed_format() {
+        let text = "[Events]\nFormat: Text\nDialogue: こんにちは\n";
+        let subtitle = Subtitle::parse(Format::Ass, text);
+        assert!(cues(&subtitle).is_empty());
+        assert_eq!(subtitle.render(), text);
+    }
+
+    #[test]
+    fn encodes_line_breaks() {
+        let subtitle = Subtitle::parse(Format::Srt, "");
+        assert_eq!(subtitle.encode("a\nb"), "a\\Nb");
+        assert_eq!(subtitle.decode(" a\\Nb\n"), "a\nb");
+
+        let subtitle = Subtitle::parse(Format::Ass, "");
+        assert_eq!(subtitle.encode("a\\Nb"), "a\\Nb");
+        assert_eq!(subtitle.decode("a\nb"), "a\\Nb");
+    }
+}
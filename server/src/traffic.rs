@@ -0,0 +1,268 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::db::Database;
+use crate::schema::RegexTerm;
+
+/// Texts are collected for at most this long before they are written to disk.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Texts waiting to be written. Texts are dropped rather than slowing down translation when full.
+const QUEUE_SIZE: usize = 10_000;
+
+/// Only this many of the most recently translated texts are kept.
+const MAX_TEXTS: usize = 100_000;
+
+/// Statistics of a source text that has been translated, keyed by the text itself.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedText {
+    pub count: u64,
+    /// Time the text was last translated, in seconds since the Unix epoch.
+    pub time: u64,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CandidateKind {
+    Katakana,
+    Name,
+    Hashtag,
+}
+
+/// A frequent span of source text that may be a name missing from the dictionary.
+#[derive(Serialize)]
+pub struct Candidate {
+    pub text: String,
+    pub kind: CandidateKind,
+    pub count: u64,
+}
+
+static KATAKANA_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"[\p{Katakana}ー][\p{Katakana}ー・]{2,}").unwrap());
+
+/// One to four kanji followed by kana, e.g. `星街すいせい` or `白上フブキ`.
+static NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\p{Han}{1,4}(?:\p{Hiragana}{3,6}|\p{Katakana}[\p{Katakana}ー]{1,6})").unwrap()
+});
+
+/// Endings that mark kanji followed by kana as a verb or adjective rather than a name.
+const INFLECTION_SUFFIXES: &[&str] = &[
+    "ます",
+    "です",
+    "した",
+    "して",
+    "する",
+    "ない",
+    "たい",
+    "った",
+    "ってる",
+    "ている",
+    "てる",
+    "れる",
+    "られ",
+    "せる",
+    "ましょう",
+    "でした",
+    "だった",
+    "かった",
+    "しい",
+    "ません",
+];
+
+/// Persistent record of translated source texts.
+///
+/// Texts are recorded without waiting, and saved in batches by a background task.
+pub struct Traffic {
+    db: Arc<Database<String, RecordedText>>,
+    sender: mpsc::Sender<(String, u64)>,
+    receiver: Arc<Mutex<mpsc::Receiver<(String, u64)>>>,
+}
+
+/// Remove the least recently seen texts beyond the limit, in the order of texts for equal times.
+fn prune(map: &mut HashMap<String, RecordedText>, limit: usize) {
+    if map.len() <= limit {
+        return;
+    }
+    let mut oldest: Vec<_> = map.iter().map(|(text, x)| (x.time, text.clone())).collect();
+    let excess = map.len() - limit;
+    oldest.select_nth_unstable(excess - 1);
+    for (_, text) in &oldest[..excess] {
+        map.remove(text);
+    }
+}
+
+impl Traffic {
+    /// Open the database and start saving recorded texts. Must be called within the runtime.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let db = Arc::new(Database::open(path)?);
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (task_db, task_receiver) = (db.clone(), receiver.clone());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(err) = Self::flush(&task_db, &task_receiver) {
+                    log::error!("Cannot save traffic: {:?}", err);
+                }
+            }
+        });
+        Ok(Self {
+            db,
+            sender,
+            receiver,
+        })
+    }
+
+    /// Save the texts waiting in the queue.
+    fn flush(
+        db: &Database<String, RecordedText>,
+        receiver: &Mutex<mpsc::Receiver<(String, u64)>>,
+    ) -> anyhow::Result<()> {
+        // The queue stays locked until texts are saved, so a concurrent flush does not return
+        // before them.
+        let mut receiver = receiver.lock().unwrap();
+        let texts: Vec<_> = std::iter::from_fn(|| receiver.try_recv().ok()).collect();
+        if texts.is_empty() {
+            return Ok(());
+        }
+        Self::apply(db, texts)
+    }
+
+    /// Save recorded texts right away.
+    pub fn save(&self) -> anyhow::Result<()> {
+        Self::flush(&self.db, &self.receiver)
+    }
+
+    fn apply(db: &Database<String, RecordedText>, texts: Vec<(String, u64)>) -> anyhow::Result<()> {
+        db.db.write(|map| {
+            for (text, time) in texts {
+                let entry = map.entry(text).or_insert(RecordedText { count: 0, time });
+                entry.count += 1;
+                entry.time = entry.time.max(time);
+            }
+            prune(map, MAX_TEXTS);
+        })?;
+        db.save()
+    }
+
+    /// Record a translated text.
+    pub fn record(&self, text: &str) {
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        if self.sender.try_send((text.to_owned(), time)).is_err() {
+            log::warn!("Traffic queue is full, dropping text");
+        }
+    }
+
+    /// List spans of recorded texts that look like names but are not covered by any term, most
+    /// frequent first.
+    pub fn candidates(
+        &self,
+        terms: &[RegexTerm],
+        min_count: u64,
+    ) -> anyhow::Result<Vec<Candidate>> {
+        candidates(&self.db, terms, min_count)
+    }
+}
+
+fn is_covered(terms: &[RegexTerm], text: &str) -> bool {
+    terms.iter().any(|term| {
+        matches!(term.input.find(text), Ok(Some(m)) if m.start() == 0 && m.end() == text.len())
+    })
+}
+
+fn candidates(
+    db: &Database<String, RecordedText>,
+    terms: &[RegexTerm],
+    min_count: u64,
+) -> anyhow::Result<Vec<Candidate>> {
+    let mut counts: HashMap<(String, CandidateKind), u64> = HashMap::new();
+    for item in db.iter()? {
+        let (text, count) = (item.key, item.value.count);
+
+        let katakana = KATAKANA_REGEX
+            .find_iter(text)
+            .map(|m| m.as_str().trim_matches('・'))
+            .filter(|m| m.chars().any(|c| c != 'ー'))
+            .map(|m| (m, CandidateKind::Katakana));
+        let names = NAME_REGEX
+            .find_iter(text)
+            .map(|m| m.as_str())
+            .filter(|m| !INFLECTION_SUFFIXES.iter().any(|s| m.ends_with(s)))
+            .map(|m| (m, CandidateKind::Name));
+        let hashtags = crate::regex::HASHTAG_REGEX
+            .captures_iter(text)
+            .map(|c| (c.get(1).unwrap().as_str(), CandidateKind::Hashtag));
+
+        for (span, kind) in katakana.chain(names).chain(hashtags) {
+            *counts.entry((span.to_owned(), kind)).or_default() += count;
+        }
+    }
+
+    let mut candidates: Vec<_> = counts
+        .into_iter()
+        .filter(|(_, count)| *count >= min_count)
+        .filter(|((text, _), _)| !is_covered(terms, text))
+        .map(|((text, kind), count)| Candidate { text, kind, count })
+        .collect();
+    candidates.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.text.cmp(&b.text)));
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mines_uncovered_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let traffic = Traffic::open(dir.path().join("traffic.db")).unwrap();
+        for _ in 0..2 {
+            traffic.record("星街すいせい、ホロライブ所属 #ほしまちぎゃらりー");
+            traffic.record("新しい曲を作ってる");
+        }
+        traffic.record("ときのそらとさくらみこ");
+        traffic.save().unwrap();
+
+        let terms = vec![serde_json::from_value(serde_json::json!({
+            "input": "ホロライブ",
+            "output": "hololive",
+        }))
+        .unwrap()];
+        let candidates: Vec<_> = traffic
+            .candidates(&terms, 2)
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.text, x.kind, x.count))
+            .collect();
+        assert_eq!(
+            candidates,
+            [
+                ("ほしまちぎゃらりー".to_owned(), CandidateKind::Hashtag, 2),
+                ("星街すいせい".to_owned(), CandidateKind::Name, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn prunes_exactly_beyond_limit() {
+        let mut map: HashMap<_, _> = [("a", 1), ("b", 2), ("c", 2), ("d", 2), ("e", 3)]
+            .into_iter()
+            .map(|(text, time)| (text.to_owned(), RecordedText { count: 1, time }))
+            .collect();
+        prune(&mut map, 3);
+        let mut texts: Vec<_> = map.keys().map(String::as_str).collect();
+        texts.sort();
+        assert_eq!(texts, ["c", "d", "e"]);
+        prune(&mut map, 3);
+        assert_eq!(map.len(), 3);
+    }
+}
@@ -1,15 +1,32 @@
-use super::Translator;
+use super::{HttpClient, Translator};
 use async_trait::async_trait;
+use std::sync::Arc;
 
 pub struct BaiduTranslator {
+    client: Arc<HttpClient>,
+    base_url: String,
     appid: String,
     secret: String,
     target_lang: String,
 }
 
+const DEFAULT_BASE_URL: &str = "https://fanyi-api.baidu.com";
+
 impl BaiduTranslator {
-    pub fn new(appid: String, secret: String, target_lang: String) -> Self {
+    pub fn new(
+        client: Arc<HttpClient>,
+        base_url: Option<String>,
+        appid: String,
+        secret: String,
+        target_lang: String,
+    ) -> Self {
         Self {
+            client,
+            base_url: base_url
+                .as_deref()
+                .unwrap_or(DEFAULT_BASE_URL)
+                .trim_end_matches('/')
+                .to_owned(),
             appid,
             secret,
             target_lang,
@@ -24,12 +41,12 @@ impl Translator for BaiduTranslator {
     }
 
     async fn translate(&self, text: &str) -> anyhow::Result<String> {
-        const API_URL: &str = "https://fanyi-api.baidu.com/api/trans/vip/translate";
+        let api_url = format!("{}/api/trans/vip/translate", self.base_url);
 
         let salt = format!("{:x}", rand::random::<u32>());
         let sign = format!(
             "{:x}",
-            md5::compute(&format!("{}{}{}{}", self.appid, text, salt, self.secret))
+            md5::compute(format!("{}{}{}{}", self.appid, text, salt, self.secret))
         );
 
         #[derive(serde::Deserialize)]
@@ -38,32 +55,44 @@ impl Translator for BaiduTranslator {
         }
 
         #[derive(serde::Deserialize)]
-        #[allow(dead_code)]
-        struct Response {
-            trans_result: Vec<TransResult>,
+        #[serde(untagged)]
+        enum ApiResponse {
+            Error {
+                error_code: String,
+                error_msg: String,
+            },
+            Success {
+                trans_result: Vec<TransResult>,
+            },
         }
 
-        let client = reqwest::Client::new();
-        let body: Response = client
-            .post(API_URL)
-            .form(&[
-                ("q", text),
-                ("from", "jp"),
-                ("to", &*self.target_lang),
-                ("appid", &*self.appid),
-                ("salt", &*salt),
-                ("sign", &*sign),
-            ])
-            .send()
-            .await?
-            .json()
+        let response = self
+            .client
+            .send(|client| {
+                client.post(&api_url).form(&[
+                    ("q", text),
+                    ("from", "jp"),
+                    ("to", &*self.target_lang),
+                    ("appid", &*self.appid),
+                    ("salt", &*salt),
+                    ("sign", &*sign),
+                ])
+            })
             .await?;
+        if !response.status().is_success() {
+            anyhow::bail!("Baidu returns {}", response.status());
+        }
 
-        Ok(body
-            .trans_result
-            .into_iter()
-            .map(|v| v.dst)
-            .collect::<Vec<_>>()
-            .join("\n"))
+        match response.json().await? {
+            ApiResponse::Success { trans_result } => Ok(trans_result
+                .into_iter()
+                .map(|v| v.dst)
+                .collect::<Vec<_>>()
+                .join("\n")),
+            ApiResponse::Error {
+                error_code,
+                error_msg,
+            } => anyhow::bail!("Error {}: {}", error_code, error_msg),
+        }
     }
 }
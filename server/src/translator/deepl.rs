@@ -1,14 +1,47 @@
-use super::Translator;
+use super::{HttpClient, Translator};
 use async_trait::async_trait;
+use std::sync::Arc;
 
 pub struct DeepLTranslator {
+    client: Arc<HttpClient>,
+    base_url: String,
     auth_key: String,
     target_lang: String,
 }
 
+/// Check the status of a response, extracting the error message DeepL supplies.
+async fn check_response(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    #[derive(serde::Deserialize)]
+    struct ApiError {
+        message: String,
+    }
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    match response.json::<ApiError>().await {
+        Ok(error) => anyhow::bail!("Error {}: {}", status.as_u16(), error.message),
+        Err(_) => anyhow::bail!("DeepL returns {}", status),
+    }
+}
+
 impl DeepLTranslator {
-    pub fn new(auth_key: String, target_lang: String) -> Self {
+    pub fn new(
+        client: Arc<HttpClient>,
+        base_url: Option<String>,
+        auth_key: String,
+        target_lang: String,
+    ) -> Self {
+        // Keys of DeepL API Free end with `:fx` and only work with the free endpoint.
+        let base_url = match base_url {
+            Some(v) => v.trim_end_matches('/').to_owned(),
+            None if auth_key.ends_with(":fx") => "https://api-free.deepl.com".to_owned(),
+            None => "https://api.deepl.com".to_owned(),
+        };
         Self {
+            client,
+            base_url,
             auth_key,
             target_lang: match target_lang.as_str() {
                 "en" => "EN-US".to_owned(),
@@ -25,7 +58,7 @@ impl Translator for DeepLTranslator {
     }
 
     async fn translate(&self, text: &str) -> anyhow::Result<String> {
-        const API_URL: &str = "https://api-free.deepl.com/v2/translate";
+        let api_url = format!("{}/v2/translate", self.base_url);
 
         #[derive(serde::Deserialize)]
         struct TransResult {
@@ -38,18 +71,16 @@ impl Translator for DeepLTranslator {
             translations: Vec<TransResult>,
         }
 
-        let client = reqwest::Client::new();
-        let mut body: Response = client
-            .post(API_URL)
-            .form(&[
-                ("text", text),
-                ("target_lang", &*self.target_lang),
-                ("auth_key", &*self.auth_key),
-            ])
-            .send()
-            .await?
-            .json()
+        let response = self
+            .client
+            .send(|client| {
+                client
+                    .post(&api_url)
+                    .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+                    .form(&[("text", text), ("target_lang", &*self.target_lang)])
+            })
             .await?;
+        let mut body: Response = check_response(response).await?.json().await?;
 
         if body.translations.is_empty() {
             anyhow::bail!("DeepL returns no translations");
@@ -61,4 +92,25 @@ impl Translator for DeepLTranslator {
 
         Ok(body.translations.swap_remove(0).text)
     }
+
+    async fn billed_characters(&self) -> anyhow::Result<Option<u64>> {
+        let api_url = format!("{}/v2/usage", self.base_url);
+
+        #[derive(serde::Deserialize)]
+        struct Response {
+            character_count: u64,
+        }
+
+        let response = self
+            .client
+            .send(|client| {
+                client
+                    .get(&api_url)
+                    .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+            })
+            .await?;
+        let body: Response = check_response(response).await?.json().await?;
+
+        Ok(Some(body.character_count))
+    }
 }
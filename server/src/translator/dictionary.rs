@@ -2,12 +2,17 @@ use arcstr::Substr;
 use async_trait::async_trait;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use std::collections::HashSet;
 use std::future::Future;
 use std::ops::Range;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
+use super::normalize::{Normalized, Normalizer};
 use super::{NopTranslator, Translator};
+use crate::metrics;
 use crate::schema::{RegexTerm, TermType};
+use crate::stats::Stats;
 
 #[async_trait]
 trait Term: Send + Sync {
@@ -20,6 +25,13 @@ trait Term: Send + Sync {
         ctx: &DictionaryTranslator,
         text: &str,
     ) -> anyhow::Result<Option<(Range<usize>, Substr)>>;
+
+    /// Whether the replacement is always the matched text itself.
+    ///
+    /// Such spans are restored from the original text when the input has been normalized.
+    fn verbatim(&self) -> bool {
+        false
+    }
 }
 
 #[async_trait]
@@ -31,19 +43,27 @@ impl<T: Term + ?Sized> Term for &T {
     ) -> anyhow::Result<Option<(Range<usize>, Substr)>> {
         (**self).scan(ctx, text).await
     }
+
+    fn verbatim(&self) -> bool {
+        (**self).verbatim()
+    }
 }
 
 #[async_trait]
 impl Term for RegexTerm {
     async fn scan(
         &self,
-        _ctx: &DictionaryTranslator,
+        ctx: &DictionaryTranslator,
         text: &str,
     ) -> anyhow::Result<Option<(Range<usize>, Substr)>> {
-        Ok(self
-            .input
-            .find(text)?
-            .map(|result| (result.range(), (&self.output).into())))
+        let result = self.input.find(text)?;
+        if result.is_some() {
+            ctx.matched_types.lock().unwrap().push(self.ty);
+            if let Some(id) = &self.id {
+                ctx.matched.lock().unwrap().insert(id.clone());
+            }
+        }
+        Ok(result.map(|result| (result.range(), (&self.output).into())))
     }
 }
 
@@ -61,6 +81,10 @@ impl Term for UrlTerm {
             .find(text)
             .map(|result| (result.range(), result.as_str().into())))
     }
+
+    fn verbatim(&self) -> bool {
+        true
+    }
 }
 
 /// Identify hashtags in the text, and avoid feeding them through machine translation. Any terms
@@ -80,6 +104,10 @@ impl Term for HashtagTerm {
         };
         let dict_translator = DictionaryTranslator::new(&NopTranslator, ctx.terms);
         let translation = dict_translator.translate(&result[1]).await?;
+        let matched = dict_translator.matched.into_inner().unwrap();
+        ctx.matched.lock().unwrap().extend(matched);
+        let matched_types = dict_translator.matched_types.into_inner().unwrap();
+        ctx.matched_types.lock().unwrap().extend(matched_types);
         Ok(Some((
             result.get(0).unwrap().range(),
             arcstr::format!("#{}", translation).into(),
@@ -101,20 +129,78 @@ impl Term for EmojiTerm {
             .find(text)
             .map(|result| (result.range(), result.as_str().into())))
     }
+
+    fn verbatim(&self) -> bool {
+        true
+    }
+}
+
+/// Identify spans matching a pattern supplied by the caller, such as markup, and avoid feeding
+/// them through machine translation.
+struct ProtectedTerm<'a>(&'a Regex);
+
+#[async_trait]
+impl Term for ProtectedTerm<'_> {
+    async fn scan(
+        &self,
+        _ctx: &DictionaryTranslator,
+        text: &str,
+    ) -> anyhow::Result<Option<(Range<usize>, Substr)>> {
+        Ok(self
+            .0
+            .find(text)
+            .map(|result| (result.range(), result.as_str().into())))
+    }
+
+    fn verbatim(&self) -> bool {
+        true
+    }
+}
+
+/// Required translations of terms in a text, given to translators that follow a glossary.
+pub struct Glossary<'a> {
+    /// Pairs of the text as it appears in the source and its required translation.
+    pub terms: Vec<(Substr, Substr)>,
+    /// Descriptions of the contexts the text belongs to.
+    pub contexts: &'a [String],
+    /// The text with all terms replaced by placeholders, for translators that don't follow a
+    /// glossary.
+    pub encoded: &'a str,
+}
+
+impl Glossary<'_> {
+    /// Required translations missing from a translation.
+    pub fn missing<'b>(&'b self, translation: &'b str) -> impl Iterator<Item = &'b str> + 'b {
+        self.terms
+            .iter()
+            .map(|(_, target)| target.as_str())
+            .filter(move |target| !translation.contains(target))
+    }
 }
 
 pub struct DictionaryTranslator<'a> {
     translator: &'a dyn Translator,
     terms: &'a [RegexTerm],
+    normalizer: Option<&'a Normalizer>,
+    protected: &'a [Regex],
+    contexts: &'a [String],
+    /// Where matches of terms are recorded, with the target language.
+    stats: Option<(&'a Stats, &'a str)>,
+    /// Ids of terms that have matched, recorded once the translation succeeds.
+    matched: Mutex<HashSet<Arc<str>>>,
+    /// Types of the terms of the dictionary that have matched, counted in metrics once the
+    /// translation succeeds. Builtin terms such as URLs are not counted.
+    matched_types: Mutex<Vec<TermType>>,
 }
 
 #[derive(Debug, Clone)]
 enum Part {
     Text(Substr),
-    Term(TermType, Substr),
+    /// A matched term, with its replacement and the text it matched.
+    Term(TermType, Substr, Substr),
 }
 
-const USABLE_CHAR: &'static str = "BCDFGHJKLMNPQRSTVWXY";
+const USABLE_CHAR: &str = "BCDFGHJKLMNPQRSTVWXY";
 static REPLACEMENT_MATCHER: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"(?i)(ZM[BCDFGHJKLMNPQRSTVWXY]+Z)").unwrap());
 
@@ -146,12 +232,14 @@ impl DictionaryTranslator<'_> {
         &self,
         text: Vec<Part>,
         terms: &[T],
+        source: Option<&Normalized<'_>>,
         filter: impl Fn(&T) -> Option<TermType> + Send + Sync,
     ) -> anyhow::Result<Vec<Part>> {
         fn helper<'a, T: Term>(
             ctx: &'a DictionaryTranslator<'a>,
             mut text: Substr,
             mut terms: &'a [T],
+            source: Option<&'a Normalized<'a>>,
             out: &'a mut Vec<Part>,
             filter: &'a (impl Fn(&T) -> Option<TermType> + Send + Sync),
         ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>> {
@@ -165,9 +253,29 @@ impl DictionaryTranslator<'_> {
                                 None => break,
                                 Some(v) => v,
                             };
-                            helper(ctx, text.substr(..range.start), &terms[1..], out, filter)
-                                .await?;
-                            out.push(Part::Term(ty, replacement));
+                            let matched: Substr = match source {
+                                Some(source) => {
+                                    let offset = text.range().start;
+                                    source
+                                        .original(offset + range.start..offset + range.end)
+                                        .into()
+                                }
+                                None => text.substr(range.clone()),
+                            };
+                            let replacement = match term.verbatim() {
+                                true => matched.clone(),
+                                false => replacement,
+                            };
+                            helper(
+                                ctx,
+                                text.substr(..range.start),
+                                &terms[1..],
+                                source,
+                                out,
+                                filter,
+                            )
+                            .await?;
+                            out.push(Part::Term(ty, replacement, matched));
                             text = text.substr(range.end..);
                         }
                     }
@@ -186,7 +294,7 @@ impl DictionaryTranslator<'_> {
         let mut ret = Vec::with_capacity(text.len());
         for part in text {
             match part {
-                Part::Text(text) => helper(self, text, terms, &mut ret, &filter).await?,
+                Part::Text(text) => helper(self, text, terms, source, &mut ret, &filter).await?,
                 p => ret.push(p),
             }
         }
@@ -198,7 +306,7 @@ impl DictionaryTranslator<'_> {
         for item in transformed {
             match item {
                 Part::Text(text) => ret.push(Part::Text(text)),
-                Part::Term(ty, replacement) if filter(ty) => {
+                Part::Term(ty, replacement, _) if filter(ty) => {
                     if !replacement.is_empty() {
                         ret.push(Part::Text(replacement));
                     }
@@ -254,7 +362,7 @@ impl DictionaryTranslator<'_> {
         ret
     }
 
-    fn encode(transformed: Vec<Part>) -> (String, Vec<Vec<(TermType, Substr)>>) {
+    fn encode(transformed: Vec<Part>) -> (String, Vec<Vec<Part>>) {
         let mut builder = String::with_capacity(transformed.len());
         let mut term_list: Vec<Vec<_>> = Vec::with_capacity(transformed.len());
         let mut prev_term = false;
@@ -264,14 +372,14 @@ impl DictionaryTranslator<'_> {
                     prev_term = false;
                     builder.push_str(&text);
                 }
-                Part::Term(term, state) => {
+                part => {
                     if prev_term {
                         // Combine multiple terms into a single one, to avoid translator being confused by a long string.
-                        term_list.last_mut().unwrap().push((term, state));
+                        term_list.last_mut().unwrap().push(part);
                     } else {
                         builder.push_str(&Self::encode_replacement_string(term_list.len()));
 
-                        term_list.push(vec![(term, state)]);
+                        term_list.push(vec![part]);
                         prev_term = true;
                     }
                 }
@@ -280,7 +388,47 @@ impl DictionaryTranslator<'_> {
         (builder, term_list)
     }
 
-    fn decode(encoded: &str, term_list: Vec<Vec<(TermType, Substr)>>) -> Vec<Part> {
+    /// Leave terms in the text, and collect their translations for a glossary.
+    ///
+    /// Spans that must be kept verbatim, such as markup, are still replaced with the same
+    /// placeholders as [`Self::encode`], so the translation can be decoded either way.
+    fn inline(transformed: &[Part]) -> (String, Vec<(Substr, Substr)>) {
+        let mut builder = String::with_capacity(transformed.len());
+        let mut terms: Vec<(Substr, Substr)> = Vec::new();
+        let mut index = 0;
+        let groups =
+            transformed.chunk_by(|a, b| matches!((a, b), (Part::Term(..), Part::Term(..))));
+        for group in groups {
+            let verbatim = group.iter().any(|part| {
+                matches!(part, Part::Term(_, replacement, matched) if replacement == matched)
+            });
+            match group {
+                [Part::Text(text)] => builder.push_str(text),
+                _ if verbatim => {
+                    builder.push_str(&Self::encode_replacement_string(index));
+                    index += 1;
+                }
+                _ => {
+                    for part in group {
+                        // Terms that remove text are applied right away.
+                        if let Part::Term(_, replacement, matched) = part {
+                            if replacement.is_empty() {
+                                continue;
+                            }
+                            builder.push_str(matched);
+                            if !terms.iter().any(|(x, _)| x == matched) {
+                                terms.push((matched.clone(), replacement.clone()));
+                            }
+                        }
+                    }
+                    index += 1;
+                }
+            }
+        }
+        (builder, terms)
+    }
+
+    fn decode(encoded: &str, term_list: Vec<Vec<Part>>) -> Vec<Part> {
         let mut encoded: Substr = encoded.into();
         let mut decoded = Vec::new();
 
@@ -297,9 +445,7 @@ impl DictionaryTranslator<'_> {
                     term_list.len()
                 );
             } else {
-                for (term, replacement) in term_list[index].iter().cloned() {
-                    decoded.push(Part::Term(term, replacement));
-                }
+                decoded.extend(term_list[index].iter().cloned());
             }
             encoded = encoded.substr(x.end()..);
         }
@@ -314,7 +460,40 @@ impl DictionaryTranslator<'_> {
 
 impl<'a> DictionaryTranslator<'a> {
     pub fn new(translator: &'a dyn Translator, terms: &'a [RegexTerm]) -> Self {
-        Self { translator, terms }
+        Self {
+            translator,
+            terms,
+            normalizer: None,
+            protected: &[],
+            contexts: &[],
+            stats: None,
+            matched: Mutex::default(),
+            matched_types: Mutex::default(),
+        }
+    }
+
+    /// Describe the contexts of the text to translators that follow a glossary.
+    pub fn with_contexts(mut self, contexts: &'a [String]) -> Self {
+        self.contexts = contexts;
+        self
+    }
+
+    /// Keep spans matching any of the patterns out of terms and machine translation.
+    pub fn with_protected(mut self, protected: &'a [Regex]) -> Self {
+        self.protected = protected;
+        self
+    }
+
+    /// Record matches of terms with an id in the statistics of the target language.
+    pub fn with_stats(mut self, stats: Option<&'a Stats>, target_lang: &'a str) -> Self {
+        self.stats = stats.map(|x| (x, target_lang));
+        self
+    }
+
+    /// Normalize the input before matching terms against it.
+    pub fn with_normalizer(mut self, normalizer: Option<&'a Normalizer>) -> Self {
+        self.normalizer = normalizer;
+        self
     }
 }
 
@@ -325,35 +504,67 @@ impl Translator for DictionaryTranslator<'_> {
     }
 
     async fn translate(&self, text: &str) -> anyhow::Result<String> {
+        let normalized = self.normalizer.map(|x| x.normalize(text));
+        let input = match &normalized {
+            Some(normalized) => Substr::full(normalized.text().clone()),
+            None => text.into(),
+        };
+        let protected: Vec<_> = self.protected.iter().map(ProtectedTerm).collect();
+        let mut builtin: Vec<&dyn Term> = protected.iter().map(|x| x as &dyn Term).collect();
+        builtin.extend([&UrlTerm as &dyn Term, &HashtagTerm, &EmojiTerm]);
         let transformed = self
             .transform(
-                vec![Part::Text(text.into())],
-                &[&UrlTerm as &dyn Term, &HashtagTerm, &EmojiTerm],
+                vec![Part::Text(input)],
+                &builtin,
+                normalized.as_ref(),
                 |_| Some(TermType::Transform),
             )
             .await?;
         let transformed = self
-            .transform(transformed, &self.terms, |x| {
-                (x.ty != TermType::Postprocess).then(|| x.ty)
+            .transform(transformed, self.terms, normalized.as_ref(), |x| {
+                (x.ty != TermType::Postprocess).then_some(x.ty)
             })
             .await?;
         let preprocessed = Self::inverse_transform(transformed, |ty| ty == TermType::Preprocess);
+        // Whether terms are left in the text or replaced with placeholders is up to the
+        // translator, as it knows what its provider follows.
+        let (inlined, terms) = Self::inline(&preprocessed);
         let (encoded, list) = Self::encode(preprocessed);
+        let glossary = Glossary {
+            terms,
+            contexts: self.contexts,
+            encoded: &encoded,
+        };
         if self.translator.name() != "Nop" {
             log::info!("Translating: {}", encoded);
         }
-        let translated = self.translator.translate(&encoded).await?;
+        let translated = self
+            .translator
+            .translate_with_glossary(&inlined, &glossary)
+            .await?;
         if self.translator.name() != "Nop" {
             log::info!("Translated: {}", translated);
         }
         let decoded = Self::decode(&translated, list);
         let postprocessed = self
-            .transform(decoded, &self.terms, |x| {
-                (x.ty == TermType::Postprocess).then(|| x.ty)
+            .transform(decoded, self.terms, None, |x| {
+                (x.ty == TermType::Postprocess).then_some(x.ty)
             })
             .await?;
         let processed = Self::inverse_transform(postprocessed, |_| true);
         let mut processed = Self::concat(processed);
+        if let Some((stats, target_lang)) = self.stats {
+            for id in self.matched.lock().unwrap().drain() {
+                stats.record(&id, target_lang);
+            }
+        }
+        if self.translator.name() != "Nop" {
+            for ty in self.matched_types.lock().unwrap().drain(..) {
+                metrics::TERM_MATCHES
+                    .with_label_values(&[ty.as_str()])
+                    .inc();
+            }
+        }
         Ok(match processed.pop() {
             Some(Part::Text(text)) => text.to_string(),
             _ => String::new(),
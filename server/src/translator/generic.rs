@@ -0,0 +1,247 @@
+use super::{HttpClient, Translator};
+use crate::config::GenericConfig;
+use async_trait::async_trait;
+use reqwest::Method;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A step of a path into a JSON value.
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// Parse a JSONPath-style path such as `$.data[0]['translated text']`.
+fn parse_path(path: &str) -> anyhow::Result<Vec<Step>> {
+    let invalid = || anyhow::anyhow!("Invalid path {}", path);
+    let mut steps = Vec::new();
+    let mut rest = path.trim().strip_prefix('$').unwrap_or(path.trim());
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("['") {
+            let end = after.find("']").ok_or_else(invalid)?;
+            steps.push(Step::Key(after[..end].to_owned()));
+            rest = &after[end + 2..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            steps.push(Step::Index(
+                after[..end].trim().parse().map_err(|_| invalid())?,
+            ));
+            rest = &after[end + 1..];
+        } else {
+            // The leading dot may be omitted, as in `data.text`.
+            let after = rest.strip_prefix('.').unwrap_or(rest);
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            steps.push(Step::Key(after[..end].to_owned()));
+            rest = &after[end..];
+        }
+    }
+    Ok(steps)
+}
+
+fn select<'a>(mut value: &'a Value, path: &[Step]) -> Option<&'a Value> {
+    for step in path {
+        value = match step {
+            Step::Key(key) => value.get(key)?,
+            Step::Index(index) => value.get(index)?,
+        };
+    }
+    Some(value)
+}
+
+/// Text of a selected value. Arrays of strings are joined, as some services translate sentence
+/// by sentence.
+fn as_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(str) => Some(str.clone()),
+        Value::Array(array) => array.iter().map(|x| x.as_str()).collect(),
+        _ => None,
+    }
+}
+
+/// Translates with any HTTP service, described entirely by the configuration.
+pub struct GenericTranslator {
+    client: Arc<HttpClient>,
+    method: Method,
+    url: String,
+    headers: HashMap<String, String>,
+    form: Option<HashMap<String, String>>,
+    json: Option<Value>,
+    result: Vec<Step>,
+    error: Option<Vec<Step>>,
+    source_lang: String,
+    target_lang: String,
+}
+
+impl GenericTranslator {
+    pub fn new(
+        client: Arc<HttpClient>,
+        config: &GenericConfig,
+        target_lang: &str,
+    ) -> anyhow::Result<Self> {
+        if config.form.is_some() && config.json.is_some() {
+            anyhow::bail!("Only one of form and json can be set for the generic translator");
+        }
+        Ok(Self {
+            client,
+            method: config.method.parse()?,
+            url: config.url.clone(),
+            headers: config.headers.clone(),
+            form: config.form.clone(),
+            json: config.json.clone(),
+            result: parse_path(&config.result)?,
+            error: config.error.as_deref().map(parse_path).transpose()?,
+            source_lang: config.source.clone(),
+            target_lang: config
+                .languages
+                .get(target_lang)
+                .cloned()
+                .unwrap_or_else(|| target_lang.to_owned()),
+        })
+    }
+
+    /// Substitute `{text}`, `{source}` and `{target}` in a template, passing values through
+    /// `escape` first.
+    fn fill(&self, template: &str, text: &str, escape: impl Fn(&str) -> String) -> String {
+        template
+            .replace("{source}", &escape(&self.source_lang))
+            .replace("{target}", &escape(&self.target_lang))
+            .replace("{text}", &escape(text))
+    }
+
+    fn fill_json(&self, template: &Value, text: &str) -> Value {
+        match template {
+            Value::String(str) => Value::String(self.fill(str, text, str::to_owned)),
+            Value::Array(array) => Value::Array(
+                array
+                    .iter()
+                    .map(|value| self.fill_json(value, text))
+                    .collect(),
+            ),
+            Value::Object(object) => Value::Object(
+                object
+                    .iter()
+                    .map(|(key, value)| (key.clone(), self.fill_json(value, text)))
+                    .collect(),
+            ),
+            value => value.clone(),
+        }
+    }
+
+    fn error_message(&self, body: &Value) -> Option<String> {
+        let message = select(body, self.error.as_ref()?)?;
+        match message {
+            // Some services always include the field, and leave it empty on success.
+            Value::Null => None,
+            Value::String(str) if str.is_empty() => None,
+            Value::String(str) => Some(str.clone()),
+            value => Some(value.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl Translator for GenericTranslator {
+    fn name(&self) -> &'static str {
+        "Generic"
+    }
+
+    async fn translate(&self, text: &str) -> anyhow::Result<String> {
+        let url = self.fill(&self.url, text, |x| {
+            url::form_urlencoded::byte_serialize(x.as_bytes()).collect()
+        });
+        let headers: Vec<_> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name, self.fill(value, text, str::to_owned)))
+            .collect();
+        let form: Option<HashMap<_, _>> = self.form.as_ref().map(|form| {
+            form.iter()
+                .map(|(name, value)| (name, self.fill(value, text, str::to_owned)))
+                .collect()
+        });
+        let json = self.json.as_ref().map(|json| self.fill_json(json, text));
+
+        let response = self
+            .client
+            .send(|client| {
+                let mut builder = client.request(self.method.clone(), &url);
+                for (name, value) in &headers {
+                    builder = builder.header(name.as_str(), value);
+                }
+                if let Some(form) = &form {
+                    builder = builder.form(form);
+                }
+                if let Some(json) = &json {
+                    builder = builder.json(json);
+                }
+                builder
+            })
+            .await?;
+
+        let status = response.status();
+        let body: Option<Value> = response.json().await.ok();
+        if let Some(message) = body.as_ref().and_then(|x| self.error_message(x)) {
+            anyhow::bail!("Error {}: {}", status.as_u16(), message);
+        }
+        if !status.is_success() {
+            anyhow::bail!("Generic translator returns {}", status);
+        }
+        body.as_ref()
+            .and_then(|x| select(x, &self.result))
+            .and_then(as_text)
+            .ok_or_else(|| anyhow::anyhow!("Cannot find the translation in the response"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn text_at(value: &Value, path: &str) -> Option<String> {
+        select(value, &parse_path(path).unwrap()).and_then(as_text)
+    }
+
+    #[test]
+    fn selects_paths() {
+        let value = json!({
+            "data": [{ "translated text": "Hello" }],
+            "text": "Hi",
+        });
+        assert_eq!(
+            text_at(&value, "$.data[0]['translated text']").as_deref(),
+            Some("Hello")
+        );
+        assert_eq!(text_at(&value, "text").as_deref(), Some("Hi"));
+        assert_eq!(text_at(&value, "$.data[1]"), None);
+        assert_eq!(
+            text_at(&json!({ "data": { "text": "Hey" } }), "data.text").as_deref(),
+            Some("Hey")
+        );
+        assert_eq!(
+            text_at(&json!([["Good ", "morning"]]), "$[0][0]").as_deref(),
+            Some("Good ")
+        );
+    }
+
+    #[test]
+    fn joins_string_arrays() {
+        let value = json!({ "sentences": ["Good morning. ", "How are you?"], "mixed": ["a", 1] });
+        assert_eq!(
+            text_at(&value, "$.sentences").as_deref(),
+            Some("Good morning. How are you?")
+        );
+        assert_eq!(text_at(&value, "$.mixed"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_paths() {
+        for path in ["data.", "$.data['text", "$.data[first]", "$..data"] {
+            assert!(parse_path(path).is_err(), "{} should be invalid", path);
+        }
+    }
+}
@@ -1,13 +1,26 @@
-use super::Translator;
+use super::{HttpClient, Translator};
 use async_trait::async_trait;
+use std::sync::Arc;
 
 pub struct GoogleTranslator {
+    client: Arc<HttpClient>,
+    base_url: String,
     target_lang: String,
 }
 
+const DEFAULT_BASE_URL: &str = "https://translate.googleapis.com";
+
 impl GoogleTranslator {
-    pub fn new(target_lang: String) -> Self {
-        Self { target_lang }
+    pub fn new(client: Arc<HttpClient>, base_url: Option<String>, target_lang: String) -> Self {
+        Self {
+            client,
+            base_url: base_url
+                .as_deref()
+                .unwrap_or(DEFAULT_BASE_URL)
+                .trim_end_matches('/')
+                .to_owned(),
+            target_lang,
+        }
     }
 }
 
@@ -18,29 +31,31 @@ impl Translator for GoogleTranslator {
     }
 
     async fn translate(&self, text: &str) -> anyhow::Result<String> {
-        const API_URL: &str = "https://translate.googleapis.com/translate_a/single";
-
-        let client = reqwest::Client::new();
-        let body: serde_json::Value = client
-            .post(API_URL)
-            .form(&[
-                ("client", "gtx"),
-                ("sl", "ja"),
-                ("tl", &*self.target_lang),
-                ("dt", "t"),
-                ("q", text),
-            ])
-            .send()
-            .await?
-            .json()
+        let api_url = format!("{}/translate_a/single", self.base_url);
+
+        let response = self
+            .client
+            .send(|client| {
+                client.post(&api_url).form(&[
+                    ("client", "gtx"),
+                    ("sl", "ja"),
+                    ("tl", &*self.target_lang),
+                    ("dt", "t"),
+                    ("q", text),
+                ])
+            })
             .await?;
+        if !response.status().is_success() {
+            anyhow::bail!("Google translate returns {}", response.status());
+        }
+        let body: serde_json::Value = response.json().await?;
 
         let out = || -> Option<_> {
-            let arr = body.as_array()?.get(0)?.as_array()?;
+            let arr = body.as_array()?.first()?.as_array()?;
 
             let mut out = Vec::with_capacity(arr.len());
             for line in arr {
-                let translated = line.as_array()?.get(0)?.as_str()?;
+                let translated = line.as_array()?.first()?.as_str()?;
                 out.push(translated);
             }
             Some(out)
@@ -0,0 +1,205 @@
+use anyhow::Context;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Semaphore;
+
+use crate::config::HttpConfig;
+
+/// HTTP client shared by all translators of a provider.
+///
+/// Limits the number of concurrent requests, and retries on connection failures, `429 Too Many
+/// Requests` and server errors with exponential backoff.
+pub struct HttpClient {
+    client: Client,
+    semaphore: Semaphore,
+    retries: u32,
+    backoff: Duration,
+}
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Delay requested by the `Retry-After` header, given in seconds or as an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?;
+    let value = value.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+impl HttpClient {
+    pub fn new(config: &HttpConfig) -> anyhow::Result<Self> {
+        let mut builder = Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout))
+            .timeout(Duration::from_secs(config.timeout));
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        if let Some(path) = &config.ca_cert {
+            let pem =
+                std::fs::read(path).with_context(|| format!("Cannot load {}", path.display()))?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        if let Some(user_agent) = &config.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        let client = builder.build()?;
+        Ok(Self {
+            client,
+            semaphore: Semaphore::new(config.concurrency.max(1)),
+            retries: config.retries,
+            backoff: Duration::from_millis(config.backoff),
+        })
+    }
+
+    /// Send a request built by the supplied function, retrying it if necessary.
+    ///
+    /// Responses with other error statuses are returned as is, so the provider specific error
+    /// payload can be parsed by the caller.
+    pub async fn send(
+        &self,
+        build: impl Fn(&Client) -> RequestBuilder,
+    ) -> anyhow::Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let backoff = (self.backoff * 2u32.saturating_pow(attempt)).min(MAX_BACKOFF);
+            // The permit is released while waiting to retry, so other requests can go ahead.
+            let permit = self.semaphore.acquire().await?;
+            let result = build(&self.client).send().await;
+            drop(permit);
+            let delay = match result {
+                Ok(response) if attempt < self.retries && is_retryable(response.status()) => {
+                    log::warn!("Request failed with {}, retrying", response.status());
+                    retry_after(&response).unwrap_or(backoff).min(MAX_BACKOFF)
+                }
+                Ok(response) => return Ok(response),
+                Err(err) if attempt < self.retries && (err.is_connect() || err.is_timeout()) => {
+                    log::warn!("Request failed: {}, retrying", err);
+                    backoff
+                }
+                Err(err) => return Err(err.into()),
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use warp::Filter;
+
+    fn response(retry_after: &str) -> Response {
+        warp::http::Response::builder()
+            .status(503)
+            .header("retry-after", retry_after)
+            .body("")
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(
+            retry_after(&response("120")),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            retry_after(&response("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::ZERO)
+        );
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(3600));
+        let delay = retry_after(&response(&later)).unwrap();
+        assert!(delay > Duration::from_secs(3500) && delay <= Duration::from_secs(3600));
+        assert_eq!(retry_after(&response("soon")), None);
+    }
+
+    #[test]
+    fn retries_overload_and_server_errors() {
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_retryable(StatusCode::FORBIDDEN));
+        assert!(!is_retryable(StatusCode::OK));
+    }
+
+    #[tokio::test]
+    async fn retries_failed_requests() {
+        // Fails twice before succeeding.
+        let requests = Arc::new(AtomicU32::new(0));
+        let counter = requests.clone();
+        let route = warp::any().map(move || {
+            let status = match counter.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => warp::http::StatusCode::TOO_MANY_REQUESTS,
+                _ => warp::http::StatusCode::OK,
+            };
+            warp::reply::with_header(warp::reply::with_status("", status), "retry-after", "0")
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let url = format!("http://{}/", addr);
+
+        let config = HttpConfig {
+            retries: 2,
+            ..Default::default()
+        };
+        let client = HttpClient::new(&config).unwrap();
+        let response = client.send(|client| client.get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // Without retries left, the error response is returned as is.
+        requests.store(0, Ordering::SeqCst);
+        let config = HttpConfig {
+            retries: 1,
+            ..Default::default()
+        };
+        let client = HttpClient::new(&config).unwrap();
+        let response = client.send(|client| client.get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn releases_permit_while_waiting_to_retry() {
+        let route = warp::path!("busy")
+            .map(|| {
+                let status = warp::http::StatusCode::TOO_MANY_REQUESTS;
+                warp::reply::with_header(warp::reply::with_status("", status), "retry-after", "5")
+            })
+            .or(warp::path!("ok").map(warp::reply));
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let config = HttpConfig {
+            concurrency: 1,
+            retries: 1,
+            ..Default::default()
+        };
+        let client = Arc::new(HttpClient::new(&config).unwrap());
+        let busy = client.clone();
+        tokio::spawn(async move {
+            let url = format!("http://{}/busy", addr);
+            busy.send(|client| client.get(&url)).await
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let url = format!("http://{}/ok", addr);
+        let response = tokio::time::timeout(
+            Duration::from_secs(2),
+            client.send(|client| client.get(&url)),
+        )
+        .await
+        .expect("request waited for the retry of another")
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,87 @@
+use super::{HttpClient, Translator};
+use async_trait::async_trait;
+use quick_xml::escape::{escape, unescape};
+use std::sync::Arc;
+
+/// Translates with a LibreTranslate server, which can be self-hosted.
+pub struct LibreTranslateTranslator {
+    client: Arc<HttpClient>,
+    url: String,
+    api_key: String,
+    source_lang: String,
+    target_lang: String,
+}
+
+impl LibreTranslateTranslator {
+    pub fn new(
+        client: Arc<HttpClient>,
+        url: &str,
+        api_key: String,
+        source_lang: Option<String>,
+        target_lang: String,
+    ) -> Self {
+        Self {
+            client,
+            url: url.trim_end_matches('/').to_owned(),
+            api_key,
+            source_lang: source_lang.unwrap_or_else(|| "auto".to_owned()),
+            target_lang,
+        }
+    }
+}
+
+#[async_trait]
+impl Translator for LibreTranslateTranslator {
+    fn name(&self) -> &'static str {
+        "LibreTranslate"
+    }
+
+    async fn translate(&self, text: &str) -> anyhow::Result<String> {
+        let api_url = format!("{}/translate", self.url);
+
+        #[derive(serde::Serialize)]
+        struct Request<'a> {
+            q: &'a str,
+            source: &'a str,
+            target: &'a str,
+            format: &'static str,
+            #[serde(skip_serializing_if = "str::is_empty")]
+            api_key: &'a str,
+        }
+
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Response {
+            translated_text: String,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct ApiError {
+            error: String,
+        }
+
+        // Markup is only kept intact in HTML mode, so the text itself has to be escaped.
+        let escaped = escape(text);
+        let request = Request {
+            q: &escaped,
+            source: &self.source_lang,
+            target: &self.target_lang,
+            format: "html",
+            api_key: &self.api_key,
+        };
+        let response = self
+            .client
+            .send(|client| client.post(&api_url).json(&request))
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            match response.json::<ApiError>().await {
+                Ok(error) => anyhow::bail!("Error {}: {}", status.as_u16(), error.error),
+                Err(_) => anyhow::bail!("LibreTranslate returns {}", status),
+            }
+        }
+        let body: Response = response.json().await?;
+        Ok(unescape(&body.translated_text)?.into_owned())
+    }
+}
@@ -0,0 +1,195 @@
+use super::{Glossary, HttpClient, Translator};
+use crate::config::LlmConfig;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Translates with a chat completion model, giving it terms as a glossary instead of
+/// placeholders, which language models tend to mangle.
+pub struct LlmTranslator {
+    client: Arc<HttpClient>,
+    base_url: String,
+    api_key: String,
+    model: String,
+    temperature: Option<f64>,
+    instructions: Option<String>,
+    glossary_retries: u32,
+    target_lang: String,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+/// Check the status of a response, extracting the error message in the OpenAI format.
+async fn check_response(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    #[derive(Deserialize)]
+    struct ApiError {
+        message: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Response {
+        error: ApiError,
+    }
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    match response.json::<Response>().await {
+        Ok(body) => anyhow::bail!("Error {}: {}", status.as_u16(), body.error.message),
+        Err(_) => anyhow::bail!("LLM returns {}", status),
+    }
+}
+
+fn language_name(lang: &str) -> &str {
+    match lang {
+        "en" => "English",
+        "zh" => "Simplified Chinese",
+        "ja" => "Japanese",
+        _ => lang,
+    }
+}
+
+impl LlmTranslator {
+    pub fn new(client: Arc<HttpClient>, config: &LlmConfig, target_lang: String) -> Self {
+        Self {
+            client,
+            base_url: config
+                .base_url
+                .as_deref()
+                .unwrap_or("https://api.openai.com/v1")
+                .trim_end_matches('/')
+                .to_owned(),
+            api_key: config.api_key.clone(),
+            model: config.model.clone(),
+            temperature: config.temperature,
+            instructions: config.instructions.clone(),
+            glossary_retries: config.glossary_retries,
+            target_lang,
+        }
+    }
+
+    fn prompt(&self, glossary: Option<&Glossary<'_>>) -> String {
+        let mut prompt = format!(
+            "You translate messages of VTubers and their fans into {}. Reply with the translation \
+             only. Keep line breaks, URLs, emoji, markup and placeholders such as ZMBZ unchanged.",
+            language_name(&self.target_lang)
+        );
+        if let Some(instructions) = &self.instructions {
+            write!(prompt, "\n\n{}", instructions).unwrap();
+        }
+        let glossary = match glossary {
+            Some(v) => v,
+            None => return prompt,
+        };
+        if !glossary.contexts.is_empty() {
+            prompt.push_str("\n\nThe message is about:");
+            for context in glossary.contexts {
+                write!(prompt, "\n- {}", context).unwrap();
+            }
+        }
+        if !glossary.terms.is_empty() {
+            prompt.push_str("\n\nAlways translate these terms exactly as given:");
+            for (source, target) in &glossary.terms {
+                write!(prompt, "\n- {} => {}", source, target).unwrap();
+            }
+        }
+        prompt
+    }
+
+    async fn complete(&self, system: &str, text: &str) -> anyhow::Result<String> {
+        let api_url = format!("{}/chat/completions", self.base_url);
+
+        #[derive(Serialize)]
+        struct Request<'a> {
+            model: &'a str,
+            messages: [Message<'a>; 2],
+            #[serde(skip_serializing_if = "Option::is_none")]
+            temperature: Option<f64>,
+        }
+
+        #[derive(Deserialize)]
+        struct ResponseMessage {
+            content: String,
+        }
+
+        #[derive(Deserialize)]
+        struct Choice {
+            message: ResponseMessage,
+        }
+
+        #[derive(Deserialize)]
+        struct Response {
+            choices: Vec<Choice>,
+        }
+
+        let request = Request {
+            model: &self.model,
+            messages: [
+                Message {
+                    role: "system",
+                    content: system,
+                },
+                Message {
+                    role: "user",
+                    content: text,
+                },
+            ],
+            temperature: self.temperature,
+        };
+        let response = self
+            .client
+            .send(|client| {
+                let builder = client.post(&api_url).json(&request);
+                match self.api_key.is_empty() {
+                    true => builder,
+                    false => builder.bearer_auth(&self.api_key),
+                }
+            })
+            .await?;
+        let mut body: Response = check_response(response).await?.json().await?;
+
+        if body.choices.is_empty() {
+            anyhow::bail!("LLM returns no choices");
+        }
+        Ok(body
+            .choices
+            .swap_remove(0)
+            .message
+            .content
+            .trim()
+            .to_owned())
+    }
+}
+
+#[async_trait]
+impl Translator for LlmTranslator {
+    fn name(&self) -> &'static str {
+        "LLM"
+    }
+
+    async fn translate(&self, text: &str) -> anyhow::Result<String> {
+        self.complete(&self.prompt(None), text).await
+    }
+
+    fn follows_glossary(&self) -> bool {
+        true
+    }
+
+    fn glossary_retries(&self) -> u32 {
+        self.glossary_retries
+    }
+
+    async fn translate_with_glossary(
+        &self,
+        text: &str,
+        glossary: &Glossary<'_>,
+    ) -> anyhow::Result<String> {
+        self.complete(&self.prompt(Some(glossary)), text).await
+    }
+}
@@ -1,14 +1,30 @@
-use super::Translator;
+use super::{HttpClient, Translator};
 use async_trait::async_trait;
+use std::sync::Arc;
 
 pub struct MicrosoftTranslator {
+    client: Arc<HttpClient>,
+    base_url: String,
     api_key: String,
     target_lang: String,
 }
 
+const DEFAULT_BASE_URL: &str = "https://api.cognitive.microsofttranslator.com";
+
 impl MicrosoftTranslator {
-    pub fn new(api_key: String, target_lang: String) -> Self {
+    pub fn new(
+        client: Arc<HttpClient>,
+        base_url: Option<String>,
+        api_key: String,
+        target_lang: String,
+    ) -> Self {
         Self {
+            client,
+            base_url: base_url
+                .as_deref()
+                .unwrap_or(DEFAULT_BASE_URL)
+                .trim_end_matches('/')
+                .to_owned(),
             api_key,
             target_lang,
         }
@@ -22,8 +38,6 @@ impl Translator for MicrosoftTranslator {
     }
 
     async fn translate(&self, text: &str) -> anyhow::Result<String> {
-        const API_URL: &str = "https://api.cognitive.microsofttranslator.com/translate";
-
         #[derive(serde::Serialize)]
         struct Request<'a> {
             text: &'a str,
@@ -56,16 +70,17 @@ impl Translator for MicrosoftTranslator {
         }
 
         let api_url = reqwest::Url::parse_with_params(
-            API_URL,
+            &format!("{}/translate", self.base_url),
             &[("api-version", "3.0"), ("to", &*self.target_lang)],
-        )
-        .unwrap();
-        let client = reqwest::Client::new();
-        let body: ApiResponse = client
-            .post(api_url)
-            .header("Ocp-Apim-Subscription-Key", &*self.api_key)
-            .json(&vec![Request { text }])
-            .send()
+        )?;
+        let body: ApiResponse = self
+            .client
+            .send(|client| {
+                client
+                    .post(api_url.clone())
+                    .header("Ocp-Apim-Subscription-Key", &*self.api_key)
+                    .json(&vec![Request { text }])
+            })
             .await?
             .json()
             .await?;
@@ -1,5 +1,16 @@
 mod dictionary;
-pub use dictionary::DictionaryTranslator;
+pub use dictionary::{DictionaryTranslator, Glossary};
+
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "http")]
+pub use http::HttpClient;
+
+mod normalize;
+pub use normalize::Normalizer;
+
+mod quota;
+pub use quota::QuotaTranslator;
 
 #[cfg(feature = "google")]
 mod google;
@@ -21,6 +32,26 @@ mod deepl;
 #[cfg(feature = "deepl")]
 pub use deepl::DeepLTranslator;
 
+#[cfg(feature = "llm")]
+mod llm;
+#[cfg(feature = "llm")]
+pub use llm::LlmTranslator;
+
+#[cfg(feature = "libretranslate")]
+mod libretranslate;
+#[cfg(feature = "libretranslate")]
+pub use libretranslate::LibreTranslateTranslator;
+
+#[cfg(feature = "generic")]
+mod generic;
+#[cfg(feature = "generic")]
+pub use generic::GenericTranslator;
+
+#[cfg(feature = "process")]
+mod process;
+#[cfg(feature = "process")]
+pub use process::{Process, ProcessTranslator};
+
 use async_trait::async_trait;
 
 #[async_trait]
@@ -28,6 +59,45 @@ pub trait Translator: Send + Sync {
     fn name(&self) -> &'static str;
 
     async fn translate(&self, text: &str) -> anyhow::Result<String>;
+
+    /// Whether the translator can be told how to translate terms, so they can be left in the
+    /// text instead of being replaced with placeholders.
+    fn follows_glossary(&self) -> bool {
+        false
+    }
+
+    /// Times to retry a translation that leaves out a required translation of the glossary.
+    fn glossary_retries(&self) -> u32 {
+        0
+    }
+
+    /// Translator to use instead when this one fails with
+    /// [`QuotaExceeded`](crate::usage::QuotaExceeded). Terms are applied again for it.
+    fn fallback(&self) -> Option<&dyn Translator> {
+        None
+    }
+
+    /// Translate using the required translations of terms in the glossary.
+    ///
+    /// `text` contains the terms as they appear in the source. Translators that don't follow a
+    /// glossary translate the text with terms replaced by placeholders instead.
+    async fn translate_with_glossary(
+        &self,
+        _text: &str,
+        glossary: &Glossary<'_>,
+    ) -> anyhow::Result<String> {
+        self.translate(glossary.encoded).await
+    }
+
+    /// Characters billed by the provider in the current period, if the provider reports it.
+    async fn billed_characters(&self) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Synchronize locally tracked usage with the provider.
+    async fn sync_usage(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 pub struct NopTranslator;
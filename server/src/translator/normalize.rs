@@ -0,0 +1,257 @@
+use arcstr::ArcStr;
+use std::ops::Range;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Normalize Japanese input before terms are matched against it.
+///
+/// This folds half-width katakana and full-width alphanumerics (through NFKC), unifies Japanese
+/// wave dashes, strips invisible characters outside of emoji sequences and optionally collapses
+/// `www` laughter into a single token.
+pub struct Normalizer {
+    laughter: Option<String>,
+}
+
+/// Result of normalization, keeping track of where each part of the normalized text comes from.
+pub struct Normalized<'a> {
+    original: &'a str,
+    text: ArcStr,
+    /// Pairs of normalized and original ranges, sorted and non-overlapping.
+    segments: Vec<(Range<usize>, Range<usize>)>,
+}
+
+fn is_invisible(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{FEFF}'
+        | '\u{FE00}'..='\u{FE0F}'
+        | '\u{E0100}'..='\u{E01EF}'
+    )
+}
+
+fn is_sound_mark(c: char) -> bool {
+    // Half-width (semi-)voiced sound marks are not combining characters but still compose with the
+    // preceding katakana under NFKC.
+    matches!(c, '\u{FF9E}' | '\u{FF9F}')
+}
+
+/// Fold variants of the Japanese wave dash. ASCII tildes are left alone, as they appear in URLs
+/// and kaomoji, so this has to run before NFKC turns full-width tildes into ASCII ones.
+fn fold_wave_dash(c: char) -> char {
+    match c {
+        '\u{FF5E}' | '\u{223C}' | '\u{3030}' | '\u{301C}' => '\u{301C}',
+        c => c,
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '/' | ':' | '-' | '_' | '@')
+}
+
+impl Normalizer {
+    pub fn new(laughter: Option<String>) -> Self {
+        Self { laughter }
+    }
+
+    fn normalize_clusters(text: &str, offset: usize, out: &mut Vec<(Range<usize>, String)>) {
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            // Keep combining characters together with their base so they compose correctly.
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, c)) = chars.peek() {
+                if !is_combining_mark(c) && !is_sound_mark(c) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+
+            let normalized: String = text[start..end]
+                .chars()
+                .filter(|&c| !is_invisible(c))
+                .map(fold_wave_dash)
+                .nfkc()
+                .collect();
+            if !normalized.is_empty() {
+                out.push((offset + start..offset + end, normalized));
+            }
+        }
+    }
+
+    fn collapse_laughter(
+        segments: Vec<(Range<usize>, String)>,
+        token: &str,
+    ) -> Vec<(Range<usize>, String)> {
+        let is_laughter = |s: &str| s.eq_ignore_ascii_case("w");
+
+        let mut ret: Vec<(Range<usize>, String)> = Vec::with_capacity(segments.len());
+        let mut run = Vec::new();
+        let mut iter = segments.into_iter().peekable();
+        while let Some(segment) = iter.next() {
+            if !is_laughter(&segment.1) {
+                ret.push(segment);
+                continue;
+            }
+
+            run.push(segment);
+            if iter.peek().is_some_and(|(_, s)| is_laughter(s)) {
+                continue;
+            }
+
+            // Avoid touching English words and URLs such as `www.example.com`.
+            let before = ret.last().and_then(|(_, s)| s.chars().last());
+            let after = iter.peek().and_then(|(_, s)| s.chars().next());
+            if run.len() >= 2
+                && !before.is_some_and(is_word_char)
+                && !after.is_some_and(is_word_char)
+            {
+                let range = run[0].0.start..run[run.len() - 1].0.end;
+                ret.push((range, token.to_owned()));
+                run.clear();
+            } else {
+                ret.append(&mut run);
+            }
+        }
+        ret
+    }
+
+    pub fn normalize<'a>(&self, text: &'a str) -> Normalized<'a> {
+        let mut segments = Vec::new();
+        let mut last = 0;
+        for emoji in crate::regex::EMOJI_REGEX.find_iter(text) {
+            Self::normalize_clusters(&text[last..emoji.start()], last, &mut segments);
+            segments.push((emoji.range(), emoji.as_str().to_owned()));
+            last = emoji.end();
+        }
+        Self::normalize_clusters(&text[last..], last, &mut segments);
+
+        if let Some(token) = &self.laughter {
+            segments = Self::collapse_laughter(segments, token);
+        }
+
+        let mut builder = String::with_capacity(text.len());
+        let segments = segments
+            .into_iter()
+            .map(|(original, normalized)| {
+                let start = builder.len();
+                builder.push_str(&normalized);
+                (start..builder.len(), original)
+            })
+            .collect();
+
+        Normalized {
+            original: text,
+            text: builder.into(),
+            segments,
+        }
+    }
+}
+
+impl Normalized<'_> {
+    pub fn text(&self) -> &ArcStr {
+        &self.text
+    }
+
+    /// Map a range of the normalized text back to the corresponding range of the original text.
+    pub fn original_range(&self, range: Range<usize>) -> Range<usize> {
+        let first = self.segments.partition_point(|(n, _)| n.end <= range.start);
+        let start = self
+            .segments
+            .get(first)
+            .map_or(self.original.len(), |(_, o)| o.start);
+        if range.is_empty() {
+            return start..start;
+        }
+
+        let last = self.segments.partition_point(|(n, _)| n.start < range.end);
+        let end = match last.checked_sub(1) {
+            Some(i) => self.segments[i].1.end,
+            None => start,
+        };
+        start..end.max(start)
+    }
+
+    /// Slice of the original text that a range of the normalized text comes from.
+    pub fn original(&self, range: Range<usize>) -> &str {
+        &self.original[self.original_range(range)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_half_width_katakana() {
+        let normalizer = Normalizer::new(None);
+        let normalized = normalizer.normalize("ｽｲｾｲﾁｬﾝ ｶﾞﾝﾊﾞｯﾃ");
+        assert_eq!(normalized.text().as_str(), "スイセイチャン ガンバッテ");
+
+        // Voiced sound marks are folded together with the katakana they follow.
+        let start = normalized.text().find("ガ").unwrap();
+        assert_eq!(normalized.original(start..start + "ガ".len()), "ｶﾞ");
+    }
+
+    #[test]
+    fn folds_wave_dashes_only() {
+        let normalizer = Normalizer::new(None);
+        assert_eq!(
+            normalizer
+                .normalize("おはよ～ おはよ〰 おはよ∼")
+                .text()
+                .as_str(),
+            "おはよ〜 おはよ〜 おはよ〜"
+        );
+        let text = "https://example.com/~suisei (~_~)";
+        assert_eq!(normalizer.normalize(text).text().as_str(), text);
+    }
+
+    #[test]
+    fn keeps_emoji_sequences() {
+        let normalizer = Normalizer::new(None);
+        let text = "\u{2764}\u{FE0F} \u{1F469}\u{200D}\u{1F4BB} すい\u{200B}せい";
+        let normalized = normalizer.normalize(text);
+        assert_eq!(
+            normalized.text().as_str(),
+            "\u{2764}\u{FE0F} \u{1F469}\u{200D}\u{1F4BB} すいせい"
+        );
+    }
+
+    #[test]
+    fn collapses_laughter() {
+        let normalizer = Normalizer::new(Some("(laugh)".into()));
+        assert_eq!(
+            normalizer.normalize("かわいいｗｗｗ").text().as_str(),
+            "かわいい(laugh)"
+        );
+        // A single w and English words are left alone.
+        assert_eq!(normalizer.normalize("草w").text().as_str(), "草w");
+        assert_eq!(
+            normalizer.normalize("www.example.com").text().as_str(),
+            "www.example.com"
+        );
+
+        let normalized = normalizer.normalize("草ｗｗ!");
+        let start = normalized.text().find("(laugh)").unwrap();
+        assert_eq!(normalized.original(start..start + "(laugh)".len()), "ｗｗ");
+    }
+
+    #[test]
+    fn restores_original_spans() {
+        let normalizer = Normalizer::new(None);
+        let text = "ＡＢＣ\u{200B}と ｽｲｾｲ";
+        let normalized = normalizer.normalize(text);
+        assert_eq!(normalized.text().as_str(), "ABCと スイセイ");
+
+        assert_eq!(normalized.original(0..3), "ＡＢＣ");
+        let start = normalized.text().find("スイセイ").unwrap();
+        assert_eq!(normalized.original(start..normalized.text().len()), "ｽｲｾｲ");
+        // Stripped characters are kept in spans around them.
+        assert_eq!(normalized.original(0.."ABCと".len()), "ＡＢＣ\u{200B}と");
+        // Empty ranges map to the start of what follows.
+        let zwsp = "ＡＢＣ\u{200B}".len();
+        assert_eq!(normalized.original_range(3..3), zwsp..zwsp);
+    }
+}
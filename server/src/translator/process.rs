@@ -0,0 +1,266 @@
+use super::Translator;
+use crate::config::ProcessConfig;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::oneshot;
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<String, String>>>>>;
+
+#[derive(Serialize)]
+struct Request<'a> {
+    id: u64,
+    text: &'a str,
+    source: &'a str,
+    target: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: u64,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// A running instance of the command.
+struct Running {
+    _child: Child,
+    stdin: ChildStdin,
+    /// Requests waiting for a response from this instance.
+    pending: Pending,
+    alive: Arc<AtomicBool>,
+}
+
+/// A long-running command that translates newline-delimited JSON requests on its standard input,
+/// shared by the translators of all languages.
+///
+/// The command is started on first use, and started again after it exits.
+pub struct Process {
+    command: Vec<String>,
+    cwd: Option<PathBuf>,
+    timeout: Duration,
+    next_id: AtomicU64,
+    running: tokio::sync::Mutex<Option<Running>>,
+}
+
+impl Process {
+    pub fn new(config: &ProcessConfig) -> Self {
+        Self {
+            command: config.command.clone(),
+            cwd: config.cwd.clone(),
+            timeout: Duration::from_secs(config.timeout),
+            next_id: AtomicU64::new(0),
+            running: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    fn spawn(&self) -> anyhow::Result<Running> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Process command is empty"))?;
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true);
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|err| anyhow::anyhow!("Cannot start {}: {}", program, err))?;
+        log::info!("Started translator process {}", program);
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let pending: Pending = Default::default();
+        let alive = Arc::new(AtomicBool::new(true));
+
+        let (reader_pending, reader_alive) = (pending.clone(), alive.clone());
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let response: Response = match serde_json::from_str(&line) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        log::warn!("Invalid response from translator process: {}", err);
+                        continue;
+                    }
+                };
+                let sender = reader_pending.lock().unwrap().remove(&response.id);
+                let result = match (response.text, response.error) {
+                    (_, Some(error)) => Err(error),
+                    (Some(text), None) => Ok(text),
+                    (None, None) => Err("Response has neither text nor error".to_owned()),
+                };
+                if let Some(sender) = sender {
+                    let _ = sender.send(result);
+                }
+            }
+            log::error!("Translator process exited");
+            reader_alive.store(false, Ordering::SeqCst);
+            // Waiting requests fail as their senders are dropped.
+            reader_pending.lock().unwrap().clear();
+        });
+
+        Ok(Running {
+            _child: child,
+            stdin,
+            pending,
+            alive,
+        })
+    }
+
+    async fn translate(&self, text: &str, source: &str, target: &str) -> anyhow::Result<String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut line = serde_json::to_string(&Request {
+            id,
+            text,
+            source,
+            target,
+        })?;
+        line.push('\n');
+
+        let (sender, receiver) = oneshot::channel();
+        let pending = {
+            let mut running = self.running.lock().await;
+            if !running
+                .as_ref()
+                .is_some_and(|x| x.alive.load(Ordering::SeqCst))
+            {
+                *running = Some(self.spawn()?);
+            }
+            let running = running.as_mut().unwrap();
+            running.pending.lock().unwrap().insert(id, sender);
+            let stdin = &mut running.stdin;
+            let written = async {
+                stdin.write_all(line.as_bytes()).await?;
+                stdin.flush().await
+            };
+            if let Err(err) = written.await {
+                running.alive.store(false, Ordering::SeqCst);
+                running.pending.lock().unwrap().remove(&id);
+                anyhow::bail!("Cannot write to translator process: {}", err);
+            }
+            running.pending.clone()
+        };
+
+        match tokio::time::timeout(self.timeout, receiver).await {
+            Ok(Ok(Ok(text))) => Ok(text),
+            Ok(Ok(Err(error))) => anyhow::bail!("{}", error),
+            Ok(Err(_)) => anyhow::bail!("Translator process exited"),
+            Err(_) => {
+                pending.lock().unwrap().remove(&id);
+                anyhow::bail!("Translator process timed out");
+            }
+        }
+    }
+}
+
+/// Translates with a local command such as a script running a machine translation model.
+pub struct ProcessTranslator {
+    process: Arc<Process>,
+    source_lang: String,
+    target_lang: String,
+}
+
+impl ProcessTranslator {
+    pub fn new(process: Arc<Process>, source_lang: String, target_lang: String) -> Self {
+        Self {
+            process,
+            source_lang,
+            target_lang,
+        }
+    }
+}
+
+#[async_trait]
+impl Translator for ProcessTranslator {
+    fn name(&self) -> &'static str {
+        "Process"
+    }
+
+    async fn translate(&self, text: &str) -> anyhow::Result<String> {
+        self.process
+            .translate(text, &self.source_lang, &self.target_lang)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers `hold` after the next request, `fail` with an error and `slow` never, and exits
+    /// on `exit`.
+    const SCRIPT: &str = r#"
+        held=
+        while IFS= read -r line; do
+            id=$(echo "$line" | sed 's/.*"id":\([0-9]*\).*/\1/')
+            case "$line" in
+                *'"text":"hold"'*) held=$id ;;
+                *'"text":"fail"'*) echo "{\"id\":$id,\"error\":\"failed\"}" ;;
+                *'"text":"slow"'*) ;;
+                *'"text":"exit"'*) exit 0 ;;
+                *)
+                    echo "{\"id\":$id,\"text\":\"ok\"}"
+                    if [ -n "$held" ]; then
+                        echo "{\"id\":$held,\"text\":\"held\"}"
+                        held=
+                    fi
+                    ;;
+            esac
+        done
+    "#;
+
+    fn process() -> Process {
+        Process::new(&ProcessConfig {
+            command: vec!["sh".to_owned(), "-c".to_owned(), SCRIPT.to_owned()],
+            timeout: 1,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn routes_responses_by_id() {
+        let process = process();
+        let (held, next) = tokio::join!(process.translate("hold", "ja", "en"), async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            process.translate("next", "ja", "en").await
+        });
+        assert_eq!(held.unwrap(), "held");
+        assert_eq!(next.unwrap(), "ok");
+
+        let err = process.translate("fail", "ja", "en").await.unwrap_err();
+        assert_eq!(err.to_string(), "failed");
+    }
+
+    #[tokio::test]
+    async fn forgets_timed_out_requests() {
+        let process = process();
+        let err = process.translate("slow", "ja", "en").await.unwrap_err();
+        assert_eq!(err.to_string(), "Translator process timed out");
+        let running = process.running.lock().await;
+        assert!(running.as_ref().unwrap().pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn restarts_after_exit() {
+        let process = process();
+        let err = process.translate("exit", "ja", "en").await.unwrap_err();
+        assert_eq!(err.to_string(), "Translator process exited");
+        assert_eq!(process.translate("again", "ja", "en").await.unwrap(), "ok");
+    }
+}
@@ -0,0 +1,303 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::{Glossary, Translator};
+use crate::error::ProviderError;
+use crate::metrics;
+use crate::usage::{QuotaExceeded, Usage};
+
+/// Call a translator, with the glossary if there is one.
+async fn translate(
+    translator: &dyn Translator,
+    text: &str,
+    glossary: Option<&Glossary<'_>>,
+) -> anyhow::Result<String> {
+    match glossary {
+        Some(glossary) => translator.translate_with_glossary(text, glossary).await,
+        None => translator.translate(text).await,
+    }
+}
+
+/// Count characters sent to a translator, and enforce its monthly budget.
+pub struct QuotaTranslator {
+    inner: Box<dyn Translator>,
+    usage: Arc<Usage>,
+    limit: Option<u64>,
+    /// Synchronize the counter with the usage reported by the provider.
+    sync: bool,
+    /// Translator to use instead once the budget is reached, see [`Translator::fallback`].
+    fallback: Option<Box<dyn Translator>>,
+}
+
+impl QuotaTranslator {
+    pub fn new(
+        inner: Box<dyn Translator>,
+        usage: Arc<Usage>,
+        limit: Option<u64>,
+        sync: bool,
+        fallback: Option<Box<dyn Translator>>,
+    ) -> Self {
+        Self {
+            inner,
+            usage,
+            limit,
+            sync,
+            fallback,
+        }
+    }
+
+    /// Characters the inner translator is sent for the text.
+    fn characters(&self, text: &str, glossary: Option<&Glossary<'_>>) -> u64 {
+        let sent = match glossary {
+            Some(glossary) if !self.inner.follows_glossary() => glossary.encoded,
+            _ => text,
+        };
+        sent.chars().count() as u64
+    }
+
+    /// Count characters about to be sent to the inner translator, unless they exceed its quota.
+    fn reserve(&self, characters: u64) -> anyhow::Result<bool> {
+        self.usage
+            .reserve(self.inner.name(), characters, self.limit)
+    }
+
+    /// Call the inner translator with reserved characters, recording metrics of the provider.
+    async fn call(
+        &self,
+        text: &str,
+        glossary: Option<&Glossary<'_>>,
+        characters: u64,
+    ) -> anyhow::Result<String> {
+        let provider = self.inner.name();
+        let start = Instant::now();
+        let result = translate(&*self.inner, text, glossary).await;
+        metrics::PROVIDER_REQUESTS
+            .with_label_values(&[provider])
+            .inc();
+        metrics::PROVIDER_DURATION
+            .with_label_values(&[provider])
+            .observe(start.elapsed().as_secs_f64());
+        match &result {
+            Ok(_) => metrics::PROVIDER_CHARACTERS
+                .with_label_values(&[provider])
+                .inc_by(characters),
+            Err(err) => metrics::PROVIDER_ERRORS
+                .with_label_values(&[provider, metrics::error_kind(err)])
+                .inc(),
+        }
+        if result.is_err() {
+            self.usage.release(provider, characters)?;
+        }
+        result.map_err(|source| ProviderError { provider, source }.into())
+    }
+
+    async fn translate_with(
+        &self,
+        text: &str,
+        glossary: Option<&Glossary<'_>>,
+    ) -> anyhow::Result<String> {
+        let name = self.inner.name();
+        let characters = self.characters(text, glossary);
+        if !self.reserve(characters)? {
+            // Terms depend on the translator, so the fallback is up to the caller.
+            if self.fallback.is_none() {
+                metrics::PROVIDER_ERRORS
+                    .with_label_values(&[name, "quota"])
+                    .inc();
+            }
+            anyhow::bail!(QuotaExceeded {
+                provider: name,
+                // Reservations only fail with a limit.
+                limit: self.limit.unwrap_or_default(),
+            });
+        }
+
+        let mut translation = self.call(text, glossary, characters).await?;
+        let glossary = match glossary {
+            Some(glossary) if self.inner.follows_glossary() => glossary,
+            _ => return Ok(translation),
+        };
+        // Retries are sent to the provider like any other request, so they count towards the quota.
+        for _ in 0..self.inner.glossary_retries() {
+            let missing: Vec<_> = glossary.missing(&translation).collect();
+            if missing.is_empty() || !self.reserve(characters)? {
+                break;
+            }
+            log::warn!("{} translation misses terms {:?}, retrying", name, missing);
+            // A translation missing terms is still better than none.
+            match self.call(text, Some(glossary), characters).await {
+                Ok(v) => translation = v,
+                Err(err) => {
+                    log::warn!("{} retry failed: {:?}", name, err);
+                    break;
+                }
+            }
+        }
+        let missing: Vec<_> = glossary.missing(&translation).collect();
+        if !missing.is_empty() {
+            log::warn!("{} translation misses terms {:?}", name, missing);
+        }
+        Ok(translation)
+    }
+}
+
+#[async_trait]
+impl Translator for QuotaTranslator {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn translate(&self, text: &str) -> anyhow::Result<String> {
+        self.translate_with(text, None).await
+    }
+
+    fn follows_glossary(&self) -> bool {
+        self.inner.follows_glossary()
+    }
+
+    fn fallback(&self) -> Option<&dyn Translator> {
+        self.fallback.as_deref()
+    }
+
+    async fn translate_with_glossary(
+        &self,
+        text: &str,
+        glossary: &Glossary<'_>,
+    ) -> anyhow::Result<String> {
+        self.translate_with(text, Some(glossary)).await
+    }
+
+    async fn billed_characters(&self) -> anyhow::Result<Option<u64>> {
+        self.inner.billed_characters().await
+    }
+
+    async fn sync_usage(&self) -> anyhow::Result<()> {
+        if self.sync {
+            if let Some(characters) = self.inner.billed_characters().await? {
+                self.usage.raise(self.inner.name(), characters)?;
+            }
+        }
+        if let Some(fallback) = &self.fallback {
+            fallback.sync_usage().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arcstr::Substr;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Translator that fails on texts starting with `!`.
+    struct Echo;
+
+    #[async_trait]
+    impl Translator for Echo {
+        fn name(&self) -> &'static str {
+            "Echo"
+        }
+
+        async fn translate(&self, text: &str) -> anyhow::Result<String> {
+            match text.strip_prefix('!') {
+                Some(_) => anyhow::bail!("Provider error"),
+                None => Ok(text.to_owned()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn enforces_quota() {
+        let dir = tempfile::tempdir().unwrap();
+        let usage = Arc::new(Usage::open(dir.path().join("usage.db")).unwrap());
+        let translator = QuotaTranslator::new(Box::new(Echo), usage.clone(), Some(10), false, None);
+
+        assert_eq!(translator.translate("すいせい").await.unwrap(), "すいせい");
+        assert_eq!(usage.get("Echo").unwrap(), 4);
+
+        // Characters of failed translations are not counted.
+        let err = translator.translate("!すいせい").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ProviderError>().unwrap().provider,
+            "Echo"
+        );
+        assert_eq!(usage.get("Echo").unwrap(), 4);
+
+        let err = translator.translate("ほしまちすいせい").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<QuotaExceeded>().unwrap().limit, 10);
+        assert_eq!(usage.get("Echo").unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn offers_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let usage = Arc::new(Usage::open(dir.path().join("usage.db")).unwrap());
+        let translator =
+            QuotaTranslator::new(Box::new(Echo), usage, Some(0), false, Some(Box::new(Echo)));
+        assert_eq!(translator.name(), "Echo");
+        assert!(translator.fallback().is_some());
+        // Translating with the fallback is up to the caller, so that it can apply its terms.
+        let err = translator.translate("すいせい").await.unwrap_err();
+        assert!(err.is::<QuotaExceeded>());
+    }
+
+    /// Translator following a glossary that answers with the given results in turn.
+    struct Scripted(Mutex<VecDeque<anyhow::Result<String>>>);
+
+    #[async_trait]
+    impl Translator for Scripted {
+        fn name(&self) -> &'static str {
+            "Scripted"
+        }
+
+        async fn translate(&self, _text: &str) -> anyhow::Result<String> {
+            self.0.lock().unwrap().pop_front().expect("unexpected call")
+        }
+
+        fn follows_glossary(&self) -> bool {
+            true
+        }
+
+        fn glossary_retries(&self) -> u32 {
+            2
+        }
+    }
+
+    async fn translate_scripted(
+        results: Vec<anyhow::Result<String>>,
+    ) -> (anyhow::Result<String>, u64) {
+        let dir = tempfile::tempdir().unwrap();
+        let usage = Arc::new(Usage::open(dir.path().join("usage.db")).unwrap());
+        let inner = Scripted(Mutex::new(results.into()));
+        let translator = QuotaTranslator::new(Box::new(inner), usage.clone(), None, false, None);
+        let glossary = Glossary {
+            terms: vec![(Substr::from("すいせい"), Substr::from("Suisei"))],
+            contexts: &[],
+            encoded: "",
+        };
+        let result = translator
+            .translate_with_glossary("こんにちはすいせい", &glossary)
+            .await;
+        (result, usage.get("Scripted").unwrap())
+    }
+
+    #[tokio::test]
+    async fn retries_missing_terms() {
+        let results = vec![Ok("Hello".to_owned()), Ok("Hello Suisei".to_owned())];
+        let (result, characters) = translate_scripted(results).await;
+        assert_eq!(result.unwrap(), "Hello Suisei");
+        // Each retry is billed.
+        assert_eq!(characters, 18);
+    }
+
+    #[tokio::test]
+    async fn keeps_translation_when_retry_fails() {
+        let results = vec![Ok("Hello".to_owned()), Err(anyhow::anyhow!("Timeout"))];
+        let (result, characters) = translate_scripted(results).await;
+        assert_eq!(result.unwrap(), "Hello");
+        assert_eq!(characters, 9);
+    }
+}
@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::{Database, Keyed};
+
+/// Counters are saved at most this often.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Characters billed by a provider in a calendar month.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProviderUsage {
+    /// Month in `YYYY-MM` format, in UTC.
+    pub month: String,
+    pub characters: u64,
+}
+
+/// Error returned when a provider has used up its monthly budget.
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub provider: &'static str,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Monthly quota of {} characters for {} is exhausted",
+            self.limit, self.provider
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Current month in `YYYY-MM` format.
+fn current_month() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+
+    // Convert days since epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}", year, month)
+}
+
+/// Persistent per-provider character counters.
+///
+/// Counters are updated in memory, and saved by a background task.
+pub struct Usage {
+    db: Arc<Database<String, ProviderUsage>>,
+    /// Whether counters have changed since they were last saved.
+    dirty: Arc<AtomicBool>,
+}
+
+impl Usage {
+    /// Open the database and start saving counters. Must be called within the runtime.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let usage = Self::load(path)?;
+        let (db, dirty) = (usage.db.clone(), usage.dirty.clone());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(err) = Self::flush(&db, &dirty) {
+                    log::error!("Cannot save usage: {:?}", err);
+                }
+            }
+        });
+        Ok(usage)
+    }
+
+    /// Open the database without ever saving counters, for processes that may run alongside the
+    /// server, which would overwrite each other's counters.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            db: Arc::new(Database::open(path)?),
+            dirty: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    fn flush(db: &Database<String, ProviderUsage>, dirty: &AtomicBool) -> anyhow::Result<()> {
+        if dirty.swap(false, Ordering::Relaxed) {
+            if let Err(err) = db.save() {
+                dirty.store(true, Ordering::Relaxed);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Save changed counters right away.
+    pub fn save(&self) -> anyhow::Result<()> {
+        Self::flush(&self.db, &self.dirty)
+    }
+
+    /// Characters billed by the provider in the current month.
+    pub fn get(&self, provider: &str) -> anyhow::Result<u64> {
+        let month = current_month();
+        Ok(self
+            .db
+            .get(provider)?
+            .filter(|x| x.value.month == month)
+            .map_or(0, |x| x.value.characters))
+    }
+
+    fn update<T>(&self, provider: &str, f: impl FnOnce(&mut u64) -> T) -> anyhow::Result<T> {
+        let month = current_month();
+        let result = self.db.db.write(|map| {
+            let usage = map
+                .entry(provider.to_owned())
+                .or_insert_with(|| ProviderUsage {
+                    month: month.clone(),
+                    characters: 0,
+                });
+            if usage.month != month {
+                usage.month = month;
+                usage.characters = 0;
+            }
+            f(&mut usage.characters)
+        })?;
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(result)
+    }
+
+    /// Count characters about to be sent, unless that would exceed the limit.
+    ///
+    /// The check and the update are done at once, so concurrent requests cannot overshoot the
+    /// limit together. Returns whether the characters have been counted.
+    pub fn reserve(
+        &self,
+        provider: &str,
+        characters: u64,
+        limit: Option<u64>,
+    ) -> anyhow::Result<bool> {
+        self.update(provider, |x| {
+            let within = limit.is_none_or(|limit| *x + characters <= limit);
+            if within {
+                *x += characters;
+            }
+            within
+        })
+    }
+
+    /// Take back characters of a reservation that were not sent after all.
+    pub fn release(&self, provider: &str, characters: u64) -> anyhow::Result<()> {
+        self.update(provider, |x| *x = x.saturating_sub(characters))
+    }
+
+    /// Raise the counter to the number reported by the provider itself.
+    ///
+    /// Providers may count over a billing period other than the calendar month, so the counter is
+    /// never lowered.
+    pub fn raise(&self, provider: &str, characters: u64) -> anyhow::Result<()> {
+        self.update(provider, |x| *x = (*x).max(characters))
+    }
+
+    pub fn list(&self) -> anyhow::Result<Vec<Keyed<String, ProviderUsage>>> {
+        let month = current_month();
+        Ok(self
+            .db
+            .iter()?
+            .filter(|x| x.value.month == month)
+            .map(|x| Keyed {
+                key: x.key.clone(),
+                value: x.value.clone(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reserves_within_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let usage = Usage::open(dir.path().join("usage.db")).unwrap();
+
+        assert!(usage.reserve("DeepL", 60, Some(100)).unwrap());
+        assert!(!usage.reserve("DeepL", 50, Some(100)).unwrap());
+        assert_eq!(usage.get("DeepL").unwrap(), 60);
+        assert!(usage.reserve("DeepL", 40, Some(100)).unwrap());
+        assert!(usage.reserve("Google", 1000, None).unwrap());
+
+        usage.release("DeepL", 70).unwrap();
+        assert_eq!(usage.get("DeepL").unwrap(), 30);
+        usage.release("DeepL", 70).unwrap();
+        assert_eq!(usage.get("DeepL").unwrap(), 0);
+
+        usage.raise("DeepL", 80).unwrap();
+        usage.raise("DeepL", 20).unwrap();
+        assert_eq!(usage.get("DeepL").unwrap(), 80);
+    }
+
+    #[tokio::test]
+    async fn saves_counters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.db");
+        let usage = Usage::open(&path).unwrap();
+        usage.reserve("DeepL", 42, None).unwrap();
+        usage.save().unwrap();
+
+        let usage = Usage::open(&path).unwrap();
+        assert_eq!(usage.get("DeepL").unwrap(), 42);
+        let list = usage.list().unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].value.month, current_month());
+    }
+
+    #[test]
+    fn formats_current_month() {
+        let month = current_month();
+        assert_eq!(month.len(), 7);
+        assert_eq!(&month[4..5], "-");
+        assert!((1..=12).contains(&month[5..].parse::<u32>().unwrap()));
+    }
+}
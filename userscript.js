@@ -40,15 +40,29 @@
     return builder;
   }
 
+  function extractAuthor(element) {
+    let article = element.closest('article');
+    if (!article) {
+      return undefined;
+    }
+    for (let span of article.querySelectorAll('a[role="link"] span')) {
+      if (span.textContent.startsWith('@')) {
+        return span.textContent.substring(1);
+      }
+    }
+    return undefined;
+  }
+
   document.body.addEventListener('click', event => {
     if (acceptableTexts.indexOf(event.target.textContent) !== -1) {
       translateSpan = event.target;
       let text = extractText(event.target.parentNode.previousSibling);
+      let author = extractAuthor(event.target);
       translateSpan.textContent = 'Translating...';
       GM_xmlhttpRequest({
         method: "POST",
-        url: "http://localhost:3001/translate?to=zh",
-        data: JSON.stringify({ text }),
+        url: "http://localhost:3001/api/translate?to=zh",
+        data: JSON.stringify({ text, author }),
         headers: {
           "Content-Type": "application/json"
         },
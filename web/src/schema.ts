@@ -48,6 +48,8 @@ export interface ITerm {
   context?: FilterList;
   type?: 'preprocess' | 'transform' | 'postprocess';
   comment?: string;
+  version?: number;
+  updatedAt?: number;
 }
 
 function validateFilterList(json: any): asserts json is FilterList {
@@ -91,6 +93,8 @@ export function validate(json: any): asserts json is ITerm  {
         break;
       }
       case 'comment': if (typeof json.comment !== 'string') throw new RangeError('Invalid Term: comment must be string'); break;
+      case 'version': if (!Number.isSafeInteger(json.version)) throw new RangeError('Invalid Term: version must be number'); break;
+      case 'updatedAt': if (!Number.isSafeInteger(json.updatedAt)) throw new RangeError('Invalid Term: updatedAt must be number'); break;
       default: throw new RangeError(`Invalid Term: extra property ${key}`);
     }
   }
@@ -79,7 +79,8 @@ export function TermManager() {
     setLoading(true);
     (async () => {
       let response = await fetch('/api/term/' + term!._id, {
-        method: 'DELETE'
+        method: 'DELETE',
+        headers: { 'If-Match': `"${term.version || 0}"` },
       });
       let resp = await response.json();
       if (resp.error) return alert(resp.error);
@@ -103,7 +104,10 @@ export function TermManager() {
       } else {
         response = await fetch('/api/term/' + term!._id, {
           method: 'PUT',
-          headers: { 'Content-Type': 'application/json; charset=utf-8' },
+          headers: {
+            'Content-Type': 'application/json; charset=utf-8',
+            'If-Match': `"${edit.version || 0}"`,
+          },
           body: JSON.stringify(term!),
         });
       }